@@ -0,0 +1,182 @@
+/*
+ * chip-8 emulator core for C and C++ hosts
+ *
+ * create an emulator with chip8_new, load a game with chip8_load_rom, then call
+ * chip8_run_frame 60 times a second and draw chip8_display after each call.
+ * functions that can fail return a Chip8Result, CHIP8_RESULT_OK (0) on success and
+ * chip8_last_error describes the last failure.
+ * a Chip8 handle belongs to one thread at a time.
+ */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+/* generated from src/lib.rs by cbindgen when the crate builds, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Display size in pixels, chip8_display points at WIDTH * HEIGHT bytes.
+ */
+#define CHIP8_DISPLAY_WIDTH 64
+
+#define CHIP8_DISPLAY_HEIGHT 32
+
+/**
+ * Bytes chip8_save_state writes, the same for every game.
+ */
+#define CHIP8_STATE_SIZE 4447
+
+/**
+ * The biggest game chip8_load_rom takes.
+ */
+#define CHIP8_MAX_ROM_SIZE 3584
+
+typedef enum Chip8Result {
+  CHIP8_RESULT_OK = 0,
+  /**
+   * A pointer argument was NULL.
+   */
+  CHIP8_RESULT_NULL_POINTER = -1,
+  /**
+   * The game is empty or bigger than CHIP8_MAX_ROM_SIZE.
+   */
+  CHIP8_RESULT_BAD_ROM = -2,
+  /**
+   * The game did something no chip-8 could carry on from, see chip8_last_error.
+   * Reset or load a game or a state to carry on.
+   */
+  CHIP8_RESULT_FAULT = -3,
+  /**
+   * There are only keys 0x0 to 0xF.
+   */
+  CHIP8_RESULT_BAD_KEY = -4,
+  /**
+   * The buffer isn't a save state from this version.
+   */
+  CHIP8_RESULT_BAD_STATE = -5,
+  /**
+   * The buffer is smaller than CHIP8_STATE_SIZE.
+   */
+  CHIP8_RESULT_BUFFER_TOO_SMALL = -6,
+} Chip8Result;
+
+/**
+ * An emulator, only ever handled through a pointer.
+ */
+typedef struct Chip8 Chip8;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates an emulator with nothing loaded. Free it with chip8_free.
+ */
+struct Chip8 *chip8_new(void);
+
+/**
+ * Frees an emulator from chip8_new. NULL is ignored.
+ */
+void chip8_free(struct Chip8 *chip8);
+
+/**
+ * Resets and loads a game of len bytes. Games the built-in database knows get
+ * their quirks and speed.
+ *
+ * # Safety
+ * data must point at len readable bytes.
+ */
+enum Chip8Result chip8_load_rom(struct Chip8 *chip8, const uint8_t *data, size_t len);
+
+/**
+ * Starts the loaded game over. It stays in memory, unlike a chip8_core reset.
+ */
+enum Chip8Result chip8_reset(struct Chip8 *chip8);
+
+/**
+ * Seeds the random number generator (CXNN), takes effect from the next load or reset.
+ */
+void chip8_set_seed(struct Chip8 *chip8, uint64_t seed);
+
+/**
+ * Runs one instruction.
+ */
+enum Chip8Result chip8_tick(struct Chip8 *chip8);
+
+/**
+ * Counts the delay and sound timers down, once per 60Hz frame.
+ */
+void chip8_tick_timers(struct Chip8 *chip8);
+
+/**
+ * Runs one 60Hz frame: chip8_ticks_per_frame instructions, then the timers.
+ */
+enum Chip8Result chip8_run_frame(struct Chip8 *chip8);
+
+/**
+ * Instructions per frame, from the game database when a game is loaded (10 otherwise).
+ */
+uint32_t chip8_ticks_per_frame(const struct Chip8 *chip8);
+
+void chip8_set_ticks_per_frame(struct Chip8 *chip8, uint32_t ticks);
+
+/**
+ * Presses or releases one key, 0x0 to 0xF.
+ */
+enum Chip8Result chip8_set_key(struct Chip8 *chip8, uint8_t key, bool pressed);
+
+/**
+ * Sets every key at once, bit n = key n.
+ */
+void chip8_set_keys(struct Chip8 *chip8, uint16_t mask);
+
+/**
+ * The display, CHIP8_DISPLAY_WIDTH * CHIP8_DISPLAY_HEIGHT bytes row by row, 1 for a lit
+ * pixel and 0 for a dark one. Stays valid until the emulator is freed and changes as it runs.
+ */
+const uint8_t *chip8_display(const struct Chip8 *chip8);
+
+/**
+ * Whether the beeper is on, poll it once per frame. chip-8 has a single tone, its pitch is
+ * up to the host.
+ */
+bool chip8_sound_active(const struct Chip8 *chip8);
+
+/**
+ * Content hash of the loaded game, 0 when none is loaded.
+ */
+uint64_t chip8_rom_hash(const struct Chip8 *chip8);
+
+/**
+ * Writes a save state of CHIP8_STATE_SIZE bytes, game and quirks included.
+ *
+ * # Safety
+ * buffer must point at len writable bytes.
+ */
+enum Chip8Result chip8_save_state(struct Chip8 *chip8, uint8_t *buffer, size_t len);
+
+/**
+ * Puts the emulator back to a state from chip8_save_state. A bad state changes nothing.
+ * Only the first CHIP8_STATE_SIZE bytes are read, so a bigger buffer is fine.
+ *
+ * # Safety
+ * buffer must point at len readable bytes.
+ */
+enum Chip8Result chip8_load_state(struct Chip8 *chip8, const uint8_t *buffer, size_t len);
+
+/**
+ * What went wrong in the last call that failed, "" if nothing has. Stays valid until the
+ * next failure or until the emulator is freed.
+ */
+const char *chip8_last_error(const struct Chip8 *chip8);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHIP8_H */
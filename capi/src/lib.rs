@@ -0,0 +1,269 @@
+use chip8_core::*;
+use std::ffi::{c_char, CString};
+use std::slice;
+
+// the header is generated from this file, so the /// comments on exported items are the C docs
+
+// ticks per frame for games the database doesn't know, the same as the web frontend
+const TICKS_PER_FRAME: u32 = 10;
+
+/// Display size in pixels, chip8_display points at WIDTH * HEIGHT bytes.
+pub const CHIP8_DISPLAY_WIDTH: usize = 64;
+pub const CHIP8_DISPLAY_HEIGHT: usize = 32;
+/// Bytes chip8_save_state writes, the same for every game.
+pub const CHIP8_STATE_SIZE: usize = 4447;
+/// The biggest game chip8_load_rom takes.
+pub const CHIP8_MAX_ROM_SIZE: usize = 3584;
+// spelled out for the header, the build fails if the core ever disagrees
+const _: () = assert!(CHIP8_DISPLAY_WIDTH == SCREEN_WIDTH && CHIP8_DISPLAY_HEIGHT == SCREEN_HEIGHT);
+const _: () = assert!(CHIP8_STATE_SIZE == SAVE_STATE_SIZE);
+const _: () = assert!(CHIP8_MAX_ROM_SIZE == MAX_ROM_SIZE);
+
+/// An emulator, only ever handled through a pointer.
+pub struct Chip8 {
+    emu: Emu,
+    // the game as it was loaded, memory may have changed since if the game writes over itself
+    rom: Vec<u8>,
+    rom_db: RomDb,
+    ticks_per_frame: u32,
+    // what went wrong last, for chip8_last_error
+    error: CString,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip8Result {
+    Ok = 0,
+    /// A pointer argument was NULL.
+    NullPointer = -1,
+    /// The game is empty or bigger than CHIP8_MAX_ROM_SIZE.
+    BadRom = -2,
+    /// The game did something no chip-8 could carry on from, see chip8_last_error.
+    /// Reset or load a game or a state to carry on.
+    Fault = -3,
+    /// There are only keys 0x0 to 0xF.
+    BadKey = -4,
+    /// The buffer isn't a save state from this version.
+    BadState = -5,
+    /// The buffer is smaller than CHIP8_STATE_SIZE.
+    BufferTooSmall = -6,
+}
+
+impl Chip8 {
+    fn fail(&mut self, result: Chip8Result, message: String) -> Chip8Result {
+        // messages are ours and never hold a NUL
+        self.error = CString::new(message).unwrap_or_default();
+        result
+    }
+}
+
+/// Creates an emulator with nothing loaded. Free it with chip8_free.
+#[no_mangle]
+pub extern "C" fn chip8_new() -> Box<Chip8> {
+    Box::new(Chip8 {
+        emu: Emu::new(),
+        rom: Vec::new(),
+        rom_db: RomDb::bundled(),
+        ticks_per_frame: TICKS_PER_FRAME,
+        error: CString::default(),
+    })
+}
+
+/// Frees an emulator from chip8_new. NULL is ignored.
+#[no_mangle]
+pub extern "C" fn chip8_free(chip8: Option<Box<Chip8>>) {
+    drop(chip8);
+}
+
+/// Resets and loads a game of len bytes. Games the built-in database knows get
+/// their quirks and speed.
+///
+/// # Safety
+/// data must point at len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(chip8: Option<&mut Chip8>, data: *const u8, len: usize) -> Chip8Result {
+    let Some(chip8) = chip8 else {
+        return Chip8Result::NullPointer;
+    };
+    if data.is_null() {
+        return chip8.fail(Chip8Result::NullPointer, "No game data".to_string());
+    }
+    let rom = slice::from_raw_parts(data, len);
+    // a game that doesn't load leaves the current one running
+    let mut emu = chip8.emu.clone();
+    emu.reset();
+    if let Err(e) = emu.load(rom) {
+        return chip8.fail(Chip8Result::BadRom, e);
+    }
+    chip8.emu = emu;
+    chip8.rom = rom.to_vec();
+    let profile = chip8.rom_db.lookup(chip8.emu.rom_hash()).cloned().unwrap_or_default();
+    chip8.emu.set_quirks(profile.quirks().unwrap_or_default());
+    chip8.ticks_per_frame = profile.ticks_per_frame.unwrap_or(TICKS_PER_FRAME);
+    Chip8Result::Ok
+}
+
+/// Starts the loaded game over. It stays in memory, unlike a chip8_core reset.
+#[no_mangle]
+pub extern "C" fn chip8_reset(chip8: Option<&mut Chip8>) -> Chip8Result {
+    let Some(chip8) = chip8 else {
+        return Chip8Result::NullPointer;
+    };
+    let quirks = chip8.emu.quirks();
+    chip8.emu.reset();
+    if !chip8.rom.is_empty() {
+        chip8.emu.load(&chip8.rom).expect("chip8_load_rom checked the game fits");
+    }
+    chip8.emu.set_quirks(quirks);
+    Chip8Result::Ok
+}
+
+/// Seeds the random number generator (CXNN), takes effect from the next load or reset.
+#[no_mangle]
+pub extern "C" fn chip8_set_seed(chip8: Option<&mut Chip8>, seed: u64) {
+    if let Some(chip8) = chip8 {
+        chip8.emu.set_seed(seed);
+    }
+}
+
+/// Runs one instruction.
+#[no_mangle]
+pub extern "C" fn chip8_tick(chip8: Option<&mut Chip8>) -> Chip8Result {
+    let Some(chip8) = chip8 else {
+        return Chip8Result::NullPointer;
+    };
+    match chip8.emu.try_tick() {
+        Ok(()) => Chip8Result::Ok,
+        Err(fault) => chip8.fail(Chip8Result::Fault, fault.to_string()),
+    }
+}
+
+/// Counts the delay and sound timers down, once per 60Hz frame.
+#[no_mangle]
+pub extern "C" fn chip8_tick_timers(chip8: Option<&mut Chip8>) {
+    if let Some(chip8) = chip8 {
+        chip8.emu.tick_timers();
+    }
+}
+
+/// Runs one 60Hz frame: chip8_ticks_per_frame instructions, then the timers.
+#[no_mangle]
+pub extern "C" fn chip8_run_frame(chip8: Option<&mut Chip8>) -> Chip8Result {
+    let Some(chip8) = chip8 else {
+        return Chip8Result::NullPointer;
+    };
+    for _ in 0..chip8.ticks_per_frame {
+        if let Err(fault) = chip8.emu.try_tick() {
+            return chip8.fail(Chip8Result::Fault, fault.to_string());
+        }
+    }
+    chip8.emu.tick_timers();
+    Chip8Result::Ok
+}
+
+/// Instructions per frame, from the game database when a game is loaded (10 otherwise).
+#[no_mangle]
+pub extern "C" fn chip8_ticks_per_frame(chip8: Option<&Chip8>) -> u32 {
+    chip8.map_or(0, |chip8| chip8.ticks_per_frame)
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_set_ticks_per_frame(chip8: Option<&mut Chip8>, ticks: u32) {
+    if let Some(chip8) = chip8 {
+        chip8.ticks_per_frame = ticks;
+    }
+}
+
+/// Presses or releases one key, 0x0 to 0xF.
+#[no_mangle]
+pub extern "C" fn chip8_set_key(chip8: Option<&mut Chip8>, key: u8, pressed: bool) -> Chip8Result {
+    let Some(chip8) = chip8 else {
+        return Chip8Result::NullPointer;
+    };
+    if key as usize >= NUM_KEYS {
+        return chip8.fail(Chip8Result::BadKey, format!("There is no key {:#x}", key));
+    }
+    chip8.emu.keypress(key as usize, pressed);
+    Chip8Result::Ok
+}
+
+/// Sets every key at once, bit n = key n.
+#[no_mangle]
+pub extern "C" fn chip8_set_keys(chip8: Option<&mut Chip8>, mask: u16) {
+    if let Some(chip8) = chip8 {
+        chip8.emu.set_key_mask(mask);
+    }
+}
+
+/// The display, CHIP8_DISPLAY_WIDTH * CHIP8_DISPLAY_HEIGHT bytes row by row, 1 for a lit
+/// pixel and 0 for a dark one. Stays valid until the emulator is freed and changes as it runs.
+#[no_mangle]
+pub extern "C" fn chip8_display(chip8: Option<&Chip8>) -> *const u8 {
+    // a bool is one byte holding 0 or 1
+    chip8.map_or(std::ptr::null(), |chip8| chip8.emu.get_display().as_ptr() as *const u8)
+}
+
+/// Whether the beeper is on, poll it once per frame. chip-8 has a single tone, its pitch is
+/// up to the host.
+#[no_mangle]
+pub extern "C" fn chip8_sound_active(chip8: Option<&Chip8>) -> bool {
+    chip8.is_some_and(|chip8| chip8.emu.sound_active())
+}
+
+/// Content hash of the loaded game, 0 when none is loaded.
+#[no_mangle]
+pub extern "C" fn chip8_rom_hash(chip8: Option<&Chip8>) -> u64 {
+    chip8.map_or(0, |chip8| chip8.emu.rom_hash())
+}
+
+/// Writes a save state of CHIP8_STATE_SIZE bytes, game and quirks included.
+///
+/// # Safety
+/// buffer must point at len writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(chip8: Option<&mut Chip8>, buffer: *mut u8, len: usize) -> Chip8Result {
+    let Some(chip8) = chip8 else {
+        return Chip8Result::NullPointer;
+    };
+    if buffer.is_null() {
+        return chip8.fail(Chip8Result::NullPointer, "No buffer".to_string());
+    }
+    if len < SAVE_STATE_SIZE {
+        return chip8.fail(Chip8Result::BufferTooSmall, format!("A save state needs {} bytes", SAVE_STATE_SIZE));
+    }
+    let state = chip8.emu.save_state().to_bytes();
+    slice::from_raw_parts_mut(buffer, len)[..state.len()].copy_from_slice(&state);
+    Chip8Result::Ok
+}
+
+/// Puts the emulator back to a state from chip8_save_state. A bad state changes nothing.
+/// Only the first CHIP8_STATE_SIZE bytes are read, so a bigger buffer is fine.
+///
+/// # Safety
+/// buffer must point at len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(chip8: Option<&mut Chip8>, buffer: *const u8, len: usize) -> Chip8Result {
+    let Some(chip8) = chip8 else {
+        return Chip8Result::NullPointer;
+    };
+    if buffer.is_null() {
+        return chip8.fail(Chip8Result::NullPointer, "No buffer".to_string());
+    }
+    if len < SAVE_STATE_SIZE {
+        return chip8.fail(Chip8Result::BufferTooSmall, format!("A save state is {} bytes", SAVE_STATE_SIZE));
+    }
+    match SaveState::from_bytes(&slice::from_raw_parts(buffer, len)[..SAVE_STATE_SIZE]) {
+        Ok(state) => {
+            chip8.emu.load_state(&state);
+            Chip8Result::Ok
+        },
+        Err(e) => chip8.fail(Chip8Result::BadState, e),
+    }
+}
+
+/// What went wrong in the last call that failed, "" if nothing has. Stays valid until the
+/// next failure or until the emulator is freed.
+#[no_mangle]
+pub extern "C" fn chip8_last_error(chip8: Option<&Chip8>) -> *const c_char {
+    chip8.map_or(c"".as_ptr(), |chip8| chip8.error.as_ptr())
+}
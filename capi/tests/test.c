@@ -0,0 +1,119 @@
+/* exercises the C API against a bundled game, run with `make test` */
+/* the checks call the API, so they have to run even in release builds */
+#undef NDEBUG
+#include <assert.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "chip8.h"
+
+static size_t read_file(const char *path, uint8_t *data, size_t len) {
+    FILE *file = fopen(path, "rb");
+    if (!file) {
+        perror(path);
+        return 0;
+    }
+    size_t read = fread(data, 1, len, file);
+    fclose(file);
+    return read;
+}
+
+static int lit_pixels(const Chip8 *chip8) {
+    const uint8_t *display = chip8_display(chip8);
+    int lit = 0;
+    for (int i = 0; i < CHIP8_DISPLAY_WIDTH * CHIP8_DISPLAY_HEIGHT; i++) {
+        lit += display[i];
+    }
+    return lit;
+}
+
+int main(int argc, char **argv) {
+    const char *path = argc > 1 ? argv[1] : "../c8games/BRIX";
+    uint8_t rom[4096];
+    size_t len = read_file(path, rom, sizeof rom);
+    assert(len > 0);
+
+    Chip8 *chip8 = chip8_new();
+    chip8_set_seed(chip8, 1);
+
+    /* bad games and keys come back as error codes */
+    assert(chip8_load_rom(chip8, rom, 0) == CHIP8_RESULT_BAD_ROM);
+    assert(chip8_load_rom(chip8, rom, sizeof rom) == CHIP8_RESULT_BAD_ROM);
+    assert(strlen(chip8_last_error(chip8)) > 0);
+    assert(chip8_load_rom(NULL, rom, len) == CHIP8_RESULT_NULL_POINTER);
+    assert(chip8_set_key(chip8, 16, true) == CHIP8_RESULT_BAD_KEY);
+
+    assert(chip8_load_rom(chip8, rom, len) == CHIP8_RESULT_OK);
+    assert(chip8_rom_hash(chip8) != 0);
+    assert(chip8_ticks_per_frame(chip8) > 0);
+    assert(lit_pixels(chip8) == 0);
+
+    /* a second of play draws something */
+    for (int frame = 0; frame < 60; frame++) {
+        assert(chip8_run_frame(chip8) == CHIP8_RESULT_OK);
+    }
+    int lit = lit_pixels(chip8);
+    assert(lit > 0);
+
+    /* save, play on with a key held, then go back */
+    uint8_t state[CHIP8_STATE_SIZE];
+    assert(chip8_save_state(chip8, state, 10) == CHIP8_RESULT_BUFFER_TOO_SMALL);
+    assert(chip8_save_state(chip8, state, sizeof state) == CHIP8_RESULT_OK);
+    uint8_t display[CHIP8_DISPLAY_WIDTH * CHIP8_DISPLAY_HEIGHT];
+    memcpy(display, chip8_display(chip8), sizeof display);
+    assert(chip8_set_key(chip8, 6, true) == CHIP8_RESULT_OK);
+    int beeped = 0;
+    for (int frame = 0; frame < 600; frame++) {
+        assert(chip8_run_frame(chip8) == CHIP8_RESULT_OK);
+        beeped |= chip8_sound_active(chip8);
+    }
+    /* the ball hits bricks and the paddle */
+    assert(beeped);
+    assert(chip8_load_state(chip8, state, sizeof state) == CHIP8_RESULT_OK);
+    assert(memcmp(display, chip8_display(chip8), sizeof display) == 0);
+
+    /* a state can sit at the start of a bigger buffer, but not a smaller one */
+    uint8_t padded[CHIP8_STATE_SIZE + 16];
+    memcpy(padded, state, sizeof state);
+    assert(chip8_load_state(chip8, padded, sizeof padded) == CHIP8_RESULT_OK);
+    assert(chip8_load_state(chip8, state, 10) == CHIP8_RESULT_BUFFER_TOO_SMALL);
+
+    /* a broken state changes nothing */
+    state[0] = 'X';
+    assert(chip8_load_state(chip8, state, sizeof state) == CHIP8_RESULT_BAD_STATE);
+    assert(memcmp(display, chip8_display(chip8), sizeof display) == 0);
+
+    /* the same seed and keys give the same game, in another emulator too */
+    Chip8 *other = chip8_new();
+    chip8_set_seed(other, 1);
+    assert(chip8_load_rom(other, rom, len) == CHIP8_RESULT_OK);
+    for (int frame = 0; frame < 60; frame++) {
+        assert(chip8_run_frame(other) == CHIP8_RESULT_OK);
+    }
+    assert(memcmp(display, chip8_display(other), sizeof display) == 0);
+
+    /* faults are reported instead of crashing: 00EE returns with an empty stack */
+    uint8_t ret[] = {0x00, 0xEE};
+    assert(chip8_load_rom(other, ret, sizeof ret) == CHIP8_RESULT_OK);
+    assert(chip8_tick(other) == CHIP8_RESULT_FAULT);
+    printf("fault: %s\n", chip8_last_error(other));
+    assert(chip8_reset(other) == CHIP8_RESULT_OK);
+    assert(chip8_rom_hash(other) != 0);
+
+    /* a reset brings back the game as loaded, even after it wrote over itself:
+       I = 0x200, V0 = 0x12, store V0 at I, then jump to itself */
+    uint8_t self_modifying[] = {0xA2, 0x00, 0x60, 0x12, 0xF0, 0x55, 0x12, 0x06};
+    assert(chip8_load_rom(other, self_modifying, sizeof self_modifying) == CHIP8_RESULT_OK);
+    uint64_t hash = chip8_rom_hash(other);
+    for (int tick = 0; tick < 4; tick++) {
+        assert(chip8_tick(other) == CHIP8_RESULT_OK);
+    }
+    assert(chip8_reset(other) == CHIP8_RESULT_OK);
+    assert(chip8_rom_hash(other) == hash);
+
+    chip8_free(other);
+    chip8_free(chip8);
+    chip8_free(NULL);
+    printf("all C API checks passed (%d pixels lit after a second)\n", lit);
+    return 0;
+}
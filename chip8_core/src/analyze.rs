@@ -0,0 +1,203 @@
+use crate::{Platform, Quirks, START_ADDR};
+
+// a guess has to beat this to count, up to it it's only a hint and frontends should stick with
+// their own defaults (one weight 1 opcode lands exactly on it, that's too easy to hit by chance)
+const MIN_CONFIDENCE: f32 = 0.5;
+
+// what a scan of a game's bytes suggests about it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis {
+    pub platform: Platform,
+    // quirks to run the game with if nothing better is known
+    pub quirks: Quirks,
+    // 0.0 (no idea) to 1.0 (certain)
+    pub confidence: f32,
+    // the opcodes that led to the guess, for showing the user
+    pub evidence: Vec<&'static str>,
+}
+
+impl Analysis {
+    pub fn is_confident(&self) -> bool {
+        self.confidence > MIN_CONFIDENCE
+    }
+}
+
+// opcodes that only make sense on one platform, with how much they count
+// data mixed in with the code can look like anything, so single-byte-pattern
+// matches like DXY0 count for less than exact opcodes like 00FF
+struct Signature {
+    platform: Platform,
+    weight: u32,
+    name: &'static str,
+    matches: fn(u16) -> bool,
+}
+
+const SIGNATURES: [Signature; 14] = [
+    // SUPER-CHIP
+    Signature { platform: Platform::SuperChip, weight: 2, name: "00FF (high resolution)", matches: |op| op == 0x00FF },
+    Signature { platform: Platform::SuperChip, weight: 2, name: "00FE (low resolution)", matches: |op| op == 0x00FE },
+    Signature { platform: Platform::SuperChip, weight: 1, name: "00FD (exit)", matches: |op| op == 0x00FD },
+    Signature { platform: Platform::SuperChip, weight: 1, name: "00FB/00FC (scroll sideways)", matches: |op| op == 0x00FB || op == 0x00FC },
+    Signature { platform: Platform::SuperChip, weight: 1, name: "00CN (scroll down)", matches: |op| op & 0xFFF0 == 0x00C0 },
+    Signature { platform: Platform::SuperChip, weight: 1, name: "DXY0 (16x16 sprite)", matches: |op| op & 0xF00F == 0xD000 },
+    Signature { platform: Platform::SuperChip, weight: 1, name: "FX30 (large font)", matches: |op| op & 0xF0FF == 0xF030 },
+    Signature { platform: Platform::SuperChip, weight: 1, name: "FX75/FX85 (flag registers)", matches: |op| op & 0xF0FF == 0xF075 || op & 0xF0FF == 0xF085 },
+    // XO-CHIP
+    Signature { platform: Platform::XoChip, weight: 2, name: "F000 NNNN (long I)", matches: |op| op == 0xF000 },
+    Signature { platform: Platform::XoChip, weight: 2, name: "5XY2/5XY3 (register ranges)", matches: |op| op & 0xF00F == 0x5002 || op & 0xF00F == 0x5003 },
+    Signature { platform: Platform::XoChip, weight: 1, name: "FN01 (bitplanes)", matches: |op| op & 0xF0FF == 0xF001 },
+    Signature { platform: Platform::XoChip, weight: 1, name: "F002/FX3A (audio)", matches: |op| op == 0xF002 || op & 0xF0FF == 0xF03A },
+    Signature { platform: Platform::XoChip, weight: 1, name: "00DN (scroll up)", matches: |op| op & 0xFFF0 == 0x00D0 },
+    // COSMAC VIP, shifting VY into VX only works the way the game expects on the original interpreter
+    Signature { platform: Platform::Chip8, weight: 1, name: "8XY6/8XYE with X != Y (VIP shift)", matches: |op| is_shift(op) && x_of(op) != y_of(op) },
+];
+
+fn x_of(op: u16) -> u16 {
+    (op & 0x0F00) >> 8
+}
+
+fn y_of(op: u16) -> u16 {
+    (op & 0x00F0) >> 4
+}
+
+fn is_shift(op: u16) -> bool {
+    op & 0xF00F == 0x8006 || op & 0xF00F == 0x800E
+}
+
+// guesses which platform a game was written for by looking for opcodes only it has
+// program is the game as loaded at 0x200, see Emu::program
+pub fn analyze(program: &[u8]) -> Analysis {
+    let ops = reachable_ops(program);
+
+    let mut scores = [0u32; 3];
+    let mut evidence = Vec::new();
+    for sig in &SIGNATURES {
+        if ops.iter().any(|&op| (sig.matches)(op)) {
+            scores[platform_idx(sig.platform)] += sig.weight;
+            evidence.push(sig.name);
+        }
+    }
+
+    let [vip, schip, xochip] = scores;
+    // XO-CHIP is a superset of SUPER-CHIP, so SUPER-CHIP opcodes don't rule it out
+    let (platform, score) = if xochip > 0 && xochip * 2 >= schip {
+        (Platform::XoChip, xochip + schip)
+    } else if schip > 0 {
+        (Platform::SuperChip, schip)
+    } else if vip > 0 {
+        (Platform::Chip8, vip)
+    } else {
+        // nothing but plain chip-8 opcodes, so the platform is a safe bet
+        // but there's nothing to say which quirks the game expects
+        return Analysis {
+            platform: Platform::Chip8,
+            quirks: Quirks::default(),
+            confidence: 0.25,
+            evidence,
+        };
+    };
+    // each matching signature makes the guess more likely, without ever reaching 1
+    let confidence = score as f32 / (score as f32 + 1.0);
+    Analysis { platform, quirks: Quirks::for_platform(platform), confidence, evidence }
+}
+
+// instructions that can actually run, found by following the game's jumps, calls and skips
+// from the entry point, sprites and other data mixed in with the code would otherwise
+// turn up plenty of opcodes that never run
+fn reachable_ops(program: &[u8]) -> Vec<u16> {
+    let start = START_ADDR as usize;
+    let end = start + program.len();
+    let fetch = |addr: usize| {
+        let idx = addr - start;
+        ((program[idx] as u16) << 8) | program[idx + 1] as u16
+    };
+    let mut seen = vec![false; program.len()];
+    let mut ops = Vec::new();
+    let mut todo = vec![start];
+    while let Some(addr) = todo.pop() {
+        // jumps outside the game or onto its last byte can't be followed
+        if addr < start || addr + 1 >= end || seen[addr - start] {
+            continue;
+        }
+        seen[addr - start] = true;
+        let op = fetch(addr);
+        ops.push(op);
+        let nnn = (op & 0xFFF) as usize;
+        match op & 0xF000 {
+            // return, exit, and jumps to V0 + NNN end this path
+            0x0000 if op == 0x00EE || op == 0x00FD => (),
+            0xB000 => (),
+            0x1000 => todo.push(nnn),
+            0x2000 => {
+                todo.push(nnn);
+                todo.push(addr + 2);
+            },
+            // skips may or may not happen, follow both
+            0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xE000 => {
+                todo.push(addr + 2);
+                todo.push(addr + 4);
+            },
+            // XO-CHIP's F000 NNNN is four bytes long
+            0xF000 if op == 0xF000 => todo.push(addr + 4),
+            _ => todo.push(addr + 2),
+        }
+    }
+    ops
+}
+
+fn platform_idx(platform: Platform) -> usize {
+    match platform {
+        Platform::Chip8 => 0,
+        Platform::SuperChip => 1,
+        Platform::XoChip => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_chip8_is_only_a_hint() {
+        // V0 = 1, then jump to itself
+        let analysis = analyze(&[0x60, 0x01, 0x12, 0x02]);
+        assert_eq!(analysis.platform, Platform::Chip8);
+        assert_eq!(analysis.quirks, Quirks::default());
+        assert!(!analysis.is_confident());
+    }
+
+    #[test]
+    fn one_vip_shift_is_only_a_hint() {
+        // 8016 (V0 = V1 >> 1 on the VIP), then jump to itself
+        let analysis = analyze(&[0x80, 0x16, 0x12, 0x02]);
+        assert_eq!(analysis.platform, Platform::Chip8);
+        assert_eq!(analysis.evidence, ["8XY6/8XYE with X != Y (VIP shift)"]);
+        assert!(!analysis.is_confident());
+    }
+
+    #[test]
+    fn high_resolution_means_super_chip() {
+        // 00FF, then jump to itself
+        let analysis = analyze(&[0x00, 0xFF, 0x12, 0x02]);
+        assert_eq!(analysis.platform, Platform::SuperChip);
+        assert_eq!(analysis.quirks, Quirks::for_platform(Platform::SuperChip));
+        assert!(analysis.is_confident());
+        assert_eq!(analysis.evidence, ["00FF (high resolution)"]);
+    }
+
+    #[test]
+    fn data_after_the_code_is_ignored() {
+        // jump to itself, then bytes that would look like 00FF and F000 if they ran
+        let analysis = analyze(&[0x12, 0x00, 0x00, 0xFF, 0xF0, 0x00]);
+        assert_eq!(analysis.platform, Platform::Chip8);
+        assert!(analysis.evidence.is_empty());
+    }
+
+    #[test]
+    fn both_sides_of_a_skip_are_followed() {
+        // skip if V0 == 0 over a jump to itself, landing on F000 NNNN
+        let analysis = analyze(&[0x30, 0x00, 0x12, 0x02, 0xF0, 0x00, 0x03, 0x00, 0x12, 0x08]);
+        assert_eq!(analysis.platform, Platform::XoChip);
+        assert_eq!(analysis.evidence, ["F000 NNNN (long I)"]);
+    }
+}
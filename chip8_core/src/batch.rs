@@ -0,0 +1,319 @@
+use crate::romdb::Fnv;
+use crate::{blank_ram, check_rom, Core, Fault, Quirks, Regs, NUM_KEYS, RAM_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH, START_ADDR};
+use rand::random;
+use std::thread;
+
+const SCREEN_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+const DEFAULT_TICKS_PER_FRAME: u32 = 5;
+
+// why an instance went back to the start of its game during the last step
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ending {
+    // the game jumped to itself, which is how most chip-8 games stop
+    Finished,
+    // the game did something no chip-8 could carry on from
+    Fault(Fault),
+}
+
+// many copies of one game running side by side, for training and search where thousands of
+// short runs matter more than any single one
+// each piece of state lives in its own array (instance n is index n of every one of them) so
+// a whole batch is a handful of allocations, and it steps through the same Core code as Emu
+pub struct BatchEmu {
+    setup: Setup,
+    regs: Vec<Regs>,
+    ram: Vec<[u8; RAM_SIZE]>,
+    screens: Vec<[bool; SCREEN_SIZE]>,
+    keys: Vec<[bool; NUM_KEYS]>,
+    episodes: Vec<u32>, // how many times each instance has started over
+    endings: Vec<Option<Ending>>, // what happened to each instance during the last step
+}
+
+// what every instance shares, read by all threads at once
+struct Setup {
+    rom: Vec<u8>,
+    quirks: Quirks,
+    ticks_per_frame: u32,
+    seed: u64,
+}
+
+// one thread's share of the batch, first is the index of its first instance
+struct Lanes<'a> {
+    first: usize,
+    regs: &'a mut [Regs],
+    ram: &'a mut [[u8; RAM_SIZE]],
+    screens: &'a mut [[bool; SCREEN_SIZE]],
+    keys: &'a [[bool; NUM_KEYS]],
+    episodes: &'a mut [u32],
+    endings: &'a mut [Option<Ending>],
+}
+
+impl BatchEmu {
+    pub fn new(rom: &[u8], count: usize) -> Result<Self, String> {
+        check_rom(rom)?;
+        let mut batch = Self {
+            setup: Setup {
+                rom: rom.to_vec(),
+                quirks: Quirks::default(),
+                ticks_per_frame: DEFAULT_TICKS_PER_FRAME,
+                seed: random(),
+            },
+            regs: Vec::with_capacity(count),
+            ram: Vec::with_capacity(count),
+            screens: vec![[false; SCREEN_SIZE]; count],
+            keys: vec![[false; NUM_KEYS]; count],
+            episodes: vec![0; count],
+            endings: vec![None; count],
+        };
+        for i in 0..count {
+            batch.regs.push(Regs::new(batch.setup.episode_seed(i, 0)));
+            batch.ram.push(batch.setup.boot_ram());
+        }
+        Ok(batch)
+    }
+    // quirks are configuration, changing them doesn't restart anything
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.setup.quirks = quirks;
+    }
+
+    pub fn set_ticks_per_frame(&mut self, ticks: u32) {
+        self.setup.ticks_per_frame = ticks;
+    }
+
+    pub fn len(&self) -> usize {
+        self.regs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regs.is_empty()
+    }
+    // starts every instance over from the beginning
+    // instance n's random numbers come from the seed, n and its episode count, so a batch
+    // reset with the same seed plays out the same way again given the same keys
+    pub fn reset_all(&mut self, seed: u64) {
+        self.setup.seed = seed;
+        for i in 0..self.len() {
+            self.episodes[i] = 0;
+            self.endings[i] = None;
+            self.regs[i] = Regs::new(self.setup.episode_seed(i, 0));
+            self.ram[i] = self.setup.boot_ram();
+            self.screens[i] = [false; SCREEN_SIZE];
+            self.keys[i] = [false; NUM_KEYS];
+        }
+    }
+    // the seed instance n starts its given episode with, the same one an Emu needs (set_seed)
+    // to replay that episode on its own
+    pub fn episode_seed(&self, instance: usize, episode: u32) -> u64 {
+        self.setup.episode_seed(instance, episode)
+    }
+    // keys held by one instance, bit n = key n
+    pub fn set_keys(&mut self, instance: usize, mask: u16) {
+        for (i, key) in self.keys[instance].iter_mut().enumerate() {
+            *key = mask & (1 << i) != 0;
+        }
+    }
+    // keys held by every instance, one mask each
+    pub fn set_all_keys(&mut self, masks: &[u16]) {
+        for (i, &mask) in masks.iter().enumerate().take(self.len()) {
+            self.set_keys(i, mask);
+        }
+    }
+    // runs every instance for the given number of frames on this thread
+    pub fn step_frames(&mut self, frames: u32) {
+        self.step_frames_threaded(frames, 1);
+    }
+    // the same as step_frames but spread over several threads, each taking a run of instances
+    // instances never affect each other so the result is the same whatever the thread count
+    pub fn step_frames_threaded(&mut self, frames: u32, threads: usize) {
+        if self.is_empty() {
+            return;
+        }
+        let size = self.len().div_ceil(threads.max(1));
+        let setup = &self.setup;
+        let mut regs = self.regs.chunks_mut(size);
+        let mut ram = self.ram.chunks_mut(size);
+        let mut screens = self.screens.chunks_mut(size);
+        let mut keys = self.keys.chunks(size);
+        let mut episodes = self.episodes.chunks_mut(size);
+        let mut endings = self.endings.chunks_mut(size);
+        let mut lanes = Vec::new();
+        while let (Some(regs), Some(ram), Some(screens), Some(keys), Some(episodes), Some(endings)) =
+            (regs.next(), ram.next(), screens.next(), keys.next(), episodes.next(), endings.next())
+        {
+            lanes.push(Lanes { first: lanes.len() * size, regs, ram, screens, keys, episodes, endings });
+        }
+        if lanes.len() == 1 {
+            lanes.pop().unwrap().run(setup, frames);
+            return;
+        }
+        thread::scope(|scope| {
+            for lane in lanes {
+                scope.spawn(move || lane.run(setup, frames));
+            }
+        });
+    }
+    // every display one after another, 2048 pixels (64x32, row by row) per instance
+    pub fn displays(&self) -> &[bool] {
+        self.screens.as_flattened()
+    }
+
+    pub fn display(&self, instance: usize) -> &[bool] {
+        &self.screens[instance]
+    }
+    // one entry per instance, Some if it started over during the last step
+    // an instance that ends stops there for the rest of that step, so its display is a fresh one
+    pub fn endings(&self) -> &[Option<Ending>] {
+        &self.endings
+    }
+
+    pub fn ram(&self, instance: usize) -> &[u8] {
+        &self.ram[instance]
+    }
+
+    pub fn episodes(&self, instance: usize) -> u32 {
+        self.episodes[instance]
+    }
+    // whether the sound timer of an instance is running
+    pub fn beeping(&self, instance: usize) -> bool {
+        self.regs[instance].st > 0
+    }
+}
+
+impl Setup {
+    fn boot_ram(&self) -> [u8; RAM_SIZE] {
+        let mut ram = blank_ram();
+        let start = START_ADDR as usize;
+        ram[start..start + self.rom.len()].copy_from_slice(&self.rom);
+        ram
+    }
+
+    fn episode_seed(&self, instance: usize, episode: u32) -> u64 {
+        let mut hash = Fnv::default();
+        hash.write(&self.seed.to_le_bytes());
+        hash.write(&(instance as u64).to_le_bytes());
+        hash.write(&episode.to_le_bytes());
+        hash.finish()
+    }
+}
+
+impl Lanes<'_> {
+    fn run(self, setup: &Setup, frames: u32) {
+        for n in 0..self.regs.len() {
+            self.endings[n] = None;
+            let mut core = Core {
+                regs: &mut self.regs[n],
+                ram: &mut self.ram[n],
+                screen: &mut self.screens[n],
+                keys: &self.keys[n],
+                quirks: setup.quirks,
+            };
+            let ending = run_frames(&mut core, setup.ticks_per_frame, frames);
+            if ending.is_some() {
+                // straight back to the start, with a new seed so the next episode isn't a rerun
+                self.endings[n] = ending;
+                self.episodes[n] += 1;
+                self.regs[n] = Regs::new(setup.episode_seed(self.first + n, self.episodes[n]));
+                self.ram[n] = setup.boot_ram();
+                self.screens[n] = [false; SCREEN_SIZE];
+            }
+        }
+    }
+}
+// steps one instance until the frames run out or its game ends
+fn run_frames(core: &mut Core, ticks: u32, frames: u32) -> Option<Ending> {
+    for _ in 0..frames {
+        for _ in 0..ticks {
+            if let Err(fault) = core.tick() {
+                return Some(Ending::Fault(fault));
+            }
+            if stuck(core) {
+                return Some(Ending::Finished);
+            }
+        }
+        core.regs.tick_timers();
+    }
+    None
+}
+// the next instruction jumps to itself, nothing but a reset gets the game out of that
+fn stuck(core: &Core) -> bool {
+    let pc = core.regs.pc as usize;
+    match core.ram.get(pc..pc + 2) {
+        Some(op) => u16::from_be_bytes([op[0], op[1]]) == 0x1000 | pc as u16,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Emu, MAX_ROM_SIZE};
+
+    const BRIX: &[u8] = include_bytes!("../../c8games/BRIX");
+
+    // a different key held on every instance, so they don't all play the same
+    fn keys(instance: usize) -> u16 {
+        1 << (4 + instance % 3)
+    }
+
+    fn brix_batch(count: usize, threads: usize) -> BatchEmu {
+        let mut batch = BatchEmu::new(BRIX, count).unwrap();
+        batch.reset_all(3);
+        batch.set_all_keys(&(0..count).map(keys).collect::<Vec<_>>());
+        for _ in 0..4 {
+            batch.step_frames_threaded(30, threads);
+        }
+        batch
+    }
+
+    #[test]
+    fn every_instance_plays_like_an_emu() {
+        let batch = brix_batch(4, 1);
+        for i in 0..batch.len() {
+            assert_eq!(batch.endings()[i], None);
+            let mut emu = Emu::new();
+            emu.set_seed(batch.episode_seed(i, 0));
+            emu.load(BRIX).unwrap();
+            emu.set_key_mask(keys(i));
+            for _ in 0..120 {
+                for _ in 0..DEFAULT_TICKS_PER_FRAME {
+                    emu.tick();
+                }
+                emu.tick_timers();
+            }
+            assert_eq!(batch.display(i), emu.get_display());
+            assert_eq!(batch.ram(i), &emu.ram[..]);
+        }
+    }
+
+    #[test]
+    fn threads_dont_change_the_result() {
+        // 7 doesn't split evenly over 3 threads
+        let one = brix_batch(7, 1);
+        let three = brix_batch(7, 3);
+        assert_eq!(one.displays(), three.displays());
+        assert_eq!(one.displays().len(), 7 * SCREEN_SIZE);
+    }
+
+    #[test]
+    fn ended_games_start_over() {
+        // a return with nothing to return to, and a jump to itself
+        for (rom, ending) in [([0x00, 0xEE], Ending::Fault(Fault::StackUnderflow)), ([0x12, 0x00], Ending::Finished)] {
+            let mut batch = BatchEmu::new(&rom, 2).unwrap();
+            batch.reset_all(1);
+            batch.step_frames(1);
+            assert_eq!(batch.endings(), [Some(ending), Some(ending)]);
+            assert_eq!(batch.episodes(0), 1);
+            assert_eq!(batch.ram(0), &batch.setup.boot_ram()[..]);
+            assert_eq!(batch.regs[0].pc, START_ADDR);
+            // the next episode doesn't reuse the last one's random numbers
+            assert_eq!(batch.regs[0].rng, batch.episode_seed(0, 1));
+            assert_ne!(batch.episode_seed(0, 1), batch.episode_seed(0, 0));
+        }
+    }
+
+    #[test]
+    fn games_too_big_for_memory_are_refused() {
+        assert!(BatchEmu::new(&[0; MAX_ROM_SIZE], 1).is_ok());
+        assert!(BatchEmu::new(&[0; MAX_ROM_SIZE + 1], 1).is_err());
+    }
+}
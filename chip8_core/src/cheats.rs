@@ -0,0 +1,287 @@
+use crate::{Emu, RAM_SIZE};
+use std::fmt;
+
+// how a byte has to compare with the last snapshot to stay a candidate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compare {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    // holds exactly this value, e.g. the number of lives on screen
+    Value(u8),
+}
+
+impl Compare {
+    // "equal", "changed", "increased", "decreased" or a number like "3" or "0x1f"
+    pub fn named(name: &str) -> Option<Compare> {
+        match name {
+            "equal" => Some(Compare::Equal),
+            "changed" => Some(Compare::Changed),
+            "increased" => Some(Compare::Increased),
+            "decreased" => Some(Compare::Decreased),
+            _ => parse_value(name).map(Compare::Value),
+        }
+    }
+
+    fn keeps(self, old: u8, new: u8) -> bool {
+        match self {
+            Compare::Equal => new == old,
+            Compare::Changed => new != old,
+            Compare::Increased => new > old,
+            Compare::Decreased => new < old,
+            Compare::Value(value) => new == value,
+        }
+    }
+}
+
+// finds where a game keeps a value by watching memory change, the usual way to make cheats:
+// start a search, lose a life, narrow by "decreased", play on a bit, narrow by "equal", and so
+// on until a few addresses are left
+pub struct MemorySearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl MemorySearch {
+    // every address is a candidate to begin with
+    pub fn new(emu: &Emu) -> MemorySearch {
+        MemorySearch {
+            snapshot: emu.ram.to_vec(),
+            candidates: (0..RAM_SIZE as u16).collect(),
+        }
+    }
+    // drops the candidates that don't compare, then takes a new snapshot to compare the
+    // next narrowing with, returns how many are left
+    pub fn narrow(&mut self, emu: &Emu, compare: Compare) -> usize {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&addr| compare.keeps(snapshot[addr as usize], emu.ram[addr as usize]));
+        self.snapshot.copy_from_slice(&emu.ram);
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+// a code that writes bytes into memory every frame
+// one byte over a game's data freezes it (lives, time, ammo), a few over its code patch it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub name: String,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    pub fn new(address: u16, bytes: Vec<u8>, name: &str) -> Result<Cheat, String> {
+        if bytes.is_empty() {
+            return Err("A cheat needs at least one byte".to_string());
+        }
+        if address as usize + bytes.len() > RAM_SIZE {
+            return Err(format!("{} bytes at {:#05x} don't fit in memory", bytes.len(), address));
+        }
+        Ok(Cheat { address, bytes, name: name.trim().to_string(), enabled: true })
+    }
+}
+
+// a game's cheats, kept as text with one per line:
+// # comment
+// 0x3a1 03 infinite lives
+// off 0x2c4 12c4 walk through walls
+// the address, the bytes in hex and a name, "off" in front keeps it around without using it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn parse(text: &str) -> Result<Cheats, String> {
+        let mut cheats = Cheats::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let cheat = parse_line(line).map_err(|e| format!("Line {}: {}", n + 1, e))?;
+            cheats.list.push(cheat);
+        }
+        Ok(cheats)
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.list.push(cheat);
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Option<Cheat> {
+        (idx < self.list.len()).then(|| self.list.remove(idx))
+    }
+
+    pub fn set_enabled(&mut self, idx: usize, enabled: bool) -> Result<(), String> {
+        match self.list.get_mut(idx) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                Ok(())
+            },
+            None => Err(format!("There is no cheat {}", idx)),
+        }
+    }
+    // once a frame, before the game runs so it always sees the cheated values
+    pub fn apply(&self, emu: &mut Emu) {
+        let mut debug = emu.debug();
+        for cheat in self.list.iter().filter(|cheat| cheat.enabled) {
+            debug.write_ram(cheat.address, &cheat.bytes).expect("addresses were checked when the cheat was made");
+        }
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.enabled {
+            write!(f, "off ")?;
+        }
+        write!(f, "{:#05x} ", self.address)?;
+        for byte in &self.bytes {
+            write!(f, "{:02x}", byte)?;
+        }
+        if !self.name.is_empty() {
+            write!(f, " {}", self.name)?;
+        }
+        Ok(())
+    }
+}
+
+// the text parse reads back
+impl fmt::Display for Cheats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for cheat in &self.list {
+            writeln!(f, "{}", cheat)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Result<Cheat, String> {
+    let (enabled, line) = match line.strip_prefix("off ") {
+        Some(rest) => (false, rest.trim_start()),
+        None => (true, line),
+    };
+    let mut parts = line.splitn(3, char::is_whitespace);
+    let address = parts.next().unwrap_or_default();
+    let address = parse_address(address)?;
+    let bytes = parse_bytes(parts.next().unwrap_or_default())?;
+    let mut cheat = Cheat::new(address, bytes, parts.next().unwrap_or_default())?;
+    cheat.enabled = enabled;
+    Ok(cheat)
+}
+
+// hex digits two to a byte, "12c4" is 0x12 then 0xc4
+pub fn parse_bytes(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(format!("Invalid bytes: {}, expected pairs of hex digits", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| format!("Invalid bytes: {}", hex)))
+        .collect()
+}
+
+// addresses are hex like the rest of the chip-8 world, with or without 0x
+pub fn parse_address(text: &str) -> Result<u16, String> {
+    let hex = text.strip_prefix("0x").unwrap_or(text);
+    match u16::from_str_radix(hex, 16) {
+        Ok(addr) if (addr as usize) < RAM_SIZE => Ok(addr),
+        _ => Err(format!("Invalid address: {}", text)),
+    }
+}
+
+// search values are decimal unless they start with 0x, since that's how scores read on screen
+fn parse_value(text: &str) -> Option<u8> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emu_with(bytes: &[(usize, u8)]) -> Emu {
+        let mut emu = Emu::new();
+        for &(addr, value) in bytes {
+            emu.ram[addr] = value;
+        }
+        emu
+    }
+
+    #[test]
+    fn search_narrows_down_to_the_address() {
+        let mut emu = emu_with(&[(0x3a1, 3), (0x3a2, 3)]);
+        let mut search = MemorySearch::new(&emu);
+        // a life lost, and something else that happens to go down too
+        emu.ram[0x3a1] = 2;
+        emu.ram[0x3a2] = 1;
+        assert_eq!(search.narrow(&emu, Compare::Decreased), 2);
+        assert_eq!(search.narrow(&emu, Compare::Equal), 2);
+        assert_eq!(search.narrow(&emu, Compare::Value(2)), 1);
+        assert_eq!(search.candidates(), [0x3a1]);
+    }
+
+    #[test]
+    fn compare_names() {
+        assert_eq!(Compare::named("increased"), Some(Compare::Increased));
+        assert_eq!(Compare::named("12"), Some(Compare::Value(12)));
+        assert_eq!(Compare::named("0x1f"), Some(Compare::Value(0x1f)));
+        assert_eq!(Compare::named("256"), None);
+        assert_eq!(Compare::named("more"), None);
+    }
+
+    #[test]
+    fn enabled_cheats_are_written_every_time() {
+        let mut cheats = Cheats::parse("0x3a1 03 lives\noff 0x2c4 12c4 walls").unwrap();
+        let mut emu = emu_with(&[(0x3a1, 1)]);
+        cheats.apply(&mut emu);
+        assert_eq!(emu.ram[0x3a1], 3);
+        assert_eq!(emu.ram[0x2c4..0x2c6], [0, 0]);
+        cheats.set_enabled(1, true).unwrap();
+        emu.ram[0x3a1] = 0;
+        cheats.apply(&mut emu);
+        assert_eq!(emu.ram[0x3a1], 3);
+        assert_eq!(emu.ram[0x2c4..0x2c6], [0x12, 0xc4]);
+        assert!(cheats.set_enabled(2, true).is_err());
+        assert_eq!(cheats.remove(0).map(|cheat| cheat.name), Some("lives".to_string()));
+        assert_eq!(cheats.remove(1), None);
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let text = "# comment\n\n3a1 03 infinite lives\noff 0x2c4 12C4 walk through walls\n0xfff ff\n";
+        let cheats = Cheats::parse(text).unwrap();
+        assert_eq!(cheats.list().len(), 3);
+        assert_eq!(cheats.list()[1], Cheat {
+            address: 0x2c4,
+            bytes: vec![0x12, 0xc4],
+            name: "walk through walls".to_string(),
+            enabled: false,
+        });
+        assert_eq!(cheats.to_string(), "0x3a1 03 infinite lives\noff 0x2c4 12c4 walk through walls\n0xfff ff\n");
+        assert_eq!(Cheats::parse(&cheats.to_string()).unwrap(), cheats);
+    }
+
+    #[test]
+    fn bad_lines_say_where() {
+        assert_eq!(Cheats::parse("0x200 00\n0x1000 00").unwrap_err(), "Line 2: Invalid address: 0x1000");
+        assert!(Cheats::parse("0x200 0").unwrap_err().contains("pairs of hex digits"));
+        assert!(Cheats::parse("0x200 zz").unwrap_err().contains("Invalid bytes"));
+        assert!(Cheats::parse("0x200").is_err());
+        // the bytes have to fit before the end of memory
+        assert!(Cheats::parse("0xfff 0102").unwrap_err().contains("don't fit"));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{Emu, NUM_KEYS, RAM_SIZE};
+use serde::Serialize;
+use std::fmt;
+
+// a copy of the CPU's registers and the rest of the state games can see, see Emu::cpu
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct CpuState {
+    pub pc: u16,
+    pub i: u16,
+    pub v: [u8; 16],
+    pub sp: u16,
+    // only the first sp entries are in use
+    pub stack: [u16; 16],
+    pub dt: u8,
+    pub st: u8,
+    pub keys: [bool; NUM_KEYS],
+}
+
+// a few lines for a register view or a log, numbers in hex like a chip-8 manual:
+// PC 0x234  I 0x30e  SP 1  DT 38  ST 00
+// V0 39 V1 00 ... V7 00
+// V8 00 ... VF 01
+// stack 0x202
+// keys 4 6
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "PC {:#05x}  I {:#05x}  SP {}  DT {:02x}  ST {:02x}", self.pc, self.i, self.sp, self.dt, self.st)?;
+        let regs: Vec<String> = self.v.iter().enumerate().map(|(n, v)| format!("V{:X} {:02x}", n, v)).collect();
+        for row in regs.chunks(8) {
+            writeln!(f, "{}", row.join(" "))?;
+        }
+        let stack: Vec<String> = self.stack[..self.sp as usize].iter().map(|addr| format!("{:#05x}", addr)).collect();
+        writeln!(f, "stack {}", stack.join(" "))?;
+        let keys: Vec<String> = (0..NUM_KEYS).filter(|&k| self.keys[k]).map(|k| format!("{:X}", k)).collect();
+        write!(f, "keys {}", keys.join(" "))
+    }
+}
+
+// changes the CPU state from the outside, for debuggers, test setups and scripting
+// kept apart from Emu's own methods so normal frontend code can't poke registers by accident
+// every setter checks its value, a bad one would make the next tick panic
+pub struct Debugger<'a> {
+    emu: &'a mut Emu,
+}
+
+impl Emu {
+    pub fn cpu(&self) -> CpuState {
+        CpuState {
+            pc: self.regs.pc,
+            i: self.regs.i_reg,
+            v: self.regs.v_reg,
+            sp: self.regs.sp,
+            stack: self.regs.stack,
+            dt: self.regs.dt,
+            st: self.regs.st,
+            keys: self.keys,
+        }
+    }
+    // len bytes of memory from addr, None if that runs past the end of the 4KB
+    pub fn read_ram(&self, addr: u16, len: usize) -> Option<&[u8]> {
+        let start = addr as usize;
+        self.ram.get(start..start.checked_add(len)?)
+    }
+
+    pub fn debug(&mut self) -> Debugger<'_> {
+        Debugger { emu: self }
+    }
+}
+
+impl Debugger<'_> {
+    // instructions are 2 bytes, so the last one starts at 0xFFE
+    pub fn set_pc(&mut self, pc: u16) -> Result<(), String> {
+        if pc as usize > RAM_SIZE - 2 {
+            return Err(format!("PC {:#05x} is outside memory", pc));
+        }
+        self.emu.regs.pc = pc;
+        Ok(())
+    }
+    // I is 12 bits on the real thing, same as the addresses ANNN can load
+    pub fn set_i(&mut self, i: u16) -> Result<(), String> {
+        if i as usize >= RAM_SIZE {
+            return Err(format!("I {:#05x} is outside memory", i));
+        }
+        self.emu.regs.i_reg = i;
+        Ok(())
+    }
+
+    pub fn set_v(&mut self, reg: usize, value: u8) -> Result<(), String> {
+        match self.emu.regs.v_reg.get_mut(reg) {
+            Some(v) => {
+                *v = value;
+                Ok(())
+            },
+            None => Err(format!("There is no register V{:X}", reg)),
+        }
+    }
+    // replaces the call stack, at most 16 return addresses, oldest first
+    // 00EE jumps to them, so they have to be places the PC could be set to
+    pub fn set_stack(&mut self, stack: &[u16]) -> Result<(), String> {
+        if stack.len() > self.emu.regs.stack.len() {
+            return Err(format!("The stack only holds {} addresses", self.emu.regs.stack.len()));
+        }
+        if let Some(addr) = stack.iter().find(|&&addr| addr as usize > RAM_SIZE - 2) {
+            return Err(format!("Return address {:#05x} is outside memory", addr));
+        }
+        self.emu.regs.stack = [0; 16];
+        self.emu.regs.stack[..stack.len()].copy_from_slice(stack);
+        self.emu.regs.sp = stack.len() as u16;
+        Ok(())
+    }
+
+    // copies data into memory at addr, the same as a game storing it there
+    pub fn write_ram(&mut self, addr: u16, data: &[u8]) -> Result<(), String> {
+        let start = addr as usize;
+        match self.emu.ram.get_mut(start..start + data.len()) {
+            Some(ram) => {
+                ram.copy_from_slice(data);
+                Ok(())
+            },
+            None => Err(format!("{} bytes at {:#05x} don't fit in memory", data.len(), addr)),
+        }
+    }
+
+    pub fn set_dt(&mut self, dt: u8) {
+        self.emu.regs.dt = dt;
+    }
+
+    pub fn set_st(&mut self, st: u8) {
+        self.emu.regs.st = st;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setters_refuse_what_would_crash_the_next_tick() {
+        let mut emu = Emu::new();
+        let mut debug = emu.debug();
+        assert!(debug.set_pc(RAM_SIZE as u16 - 2).is_ok());
+        assert!(debug.set_pc(RAM_SIZE as u16 - 1).is_err());
+        assert!(debug.set_stack(&[0x200, RAM_SIZE as u16 - 2]).is_ok());
+        assert!(debug.set_stack(&[0x200, RAM_SIZE as u16 - 1]).is_err());
+        assert!(debug.set_stack(&[0x200; 17]).is_err());
+        // a refused stack leaves the old one alone
+        assert_eq!(emu.cpu().sp, 2);
+        assert_eq!(emu.cpu().stack[1], RAM_SIZE as u16 - 2);
+    }
+
+    #[test]
+    fn write_ram_stays_inside_memory() {
+        let mut emu = Emu::new();
+        assert!(emu.debug().write_ram(RAM_SIZE as u16 - 2, &[1, 2]).is_ok());
+        assert_eq!(emu.read_ram(RAM_SIZE as u16 - 2, 2), Some(&[1, 2][..]));
+        assert!(emu.debug().write_ram(RAM_SIZE as u16 - 1, &[1, 2]).is_err());
+    }
+}
@@ -0,0 +1,353 @@
+use serde::{Deserialize, Serialize};
+use crate::{check_rom, Emu, RomProfile, NUM_KEYS, RAM_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// same as the frontends, unless the game's profile asks for something else
+const DEFAULT_TICKS_PER_FRAME: u32 = 5;
+
+// how a game keeps a number in memory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    // one decimal digit per byte, most significant first, what FX33 writes
+    #[default]
+    Bcd,
+    // a big-endian binary number
+    Binary,
+}
+
+// a number read from game memory, e.g. { address = 0x314, length = 3 } for a score FX33 stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RamValue {
+    pub address: u16,
+    #[serde(default = "one")]
+    pub length: u8,
+    #[serde(default)]
+    pub encoding: Encoding,
+}
+
+fn one() -> u8 {
+    1
+}
+
+// the game is over once a value reaches a number, e.g. lives hitting 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DoneRule {
+    #[serde(flatten)]
+    pub value: RamValue,
+    pub equals: u32,
+}
+
+// how a learning agent is scored on one game, part of its RomProfile
+// [c86e8ff63fce668c]
+// env = { score = { address = 0x314, length = 3 }, keys = [0x4, 0x6] }
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvSpec {
+    // the reward for a step is how much this went up
+    pub score: Option<RamValue>,
+    // besides this, an episode ends when the game jumps to itself (how most chip-8 games stop) or crashes
+    pub done: Option<DoneRule>,
+    // keys the agent can press, every key when not given
+    pub keys: Option<Vec<u8>>,
+}
+
+// what the agent sees after every step
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ObservationKind {
+    // the 64x32 display, one byte per pixel (0 or 1), row by row
+    #[default]
+    Display,
+    // all 4KB of memory
+    Ram,
+}
+
+// settings that aren't about any one game
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnvConfig {
+    // frames an action is held for per step, rewards are added up over them
+    pub frame_skip: u32,
+    pub observation: ObservationKind,
+    // episodes are cut short after this many frames (reported as truncated), if set
+    pub max_frames: Option<usize>,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self { frame_skip: 4, observation: ObservationKind::Display, max_frames: None }
+    }
+}
+
+// what step() returns, the same as a Gym step
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub observation: Vec<u8>,
+    pub reward: f32,
+    pub done: bool,
+    pub info: StepInfo,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StepInfo {
+    // frames since the last reset
+    pub frame: usize,
+    // the score as the game has it, when the game has a score location
+    pub score: Option<u32>,
+    // the episode was cut off by max_frames rather than ended by the game
+    pub truncated: bool,
+}
+
+// a Gym-style reinforcement learning environment around one game
+// given the same seed and the same actions it plays out exactly the same way
+pub struct Environment {
+    emu: Emu,
+    rom: Vec<u8>,
+    profile: RomProfile,
+    spec: EnvSpec,
+    config: EnvConfig,
+    ticks_per_frame: u32,
+    // key mask for each action, action 0 presses nothing
+    actions: Vec<u16>,
+    frame: usize,
+    score: Option<u32>,
+    done: bool,
+}
+
+impl Environment {
+    // the profile gives quirks, speed and the scoring rules (see RomDb::lookup)
+    pub fn new(rom: &[u8], profile: &RomProfile, config: EnvConfig) -> Result<Self, String> {
+        let spec = profile.env.clone().unwrap_or_default();
+        spec.validate()?;
+        // reset loads the game again every episode, so it has to fit from the start
+        check_rom(rom)?;
+        let keys = spec.keys.clone().unwrap_or_else(|| (0..NUM_KEYS as u8).collect());
+        let mut actions = vec![0];
+        actions.extend(keys.iter().map(|&key| 1 << key));
+        let mut env = Self {
+            emu: Emu::new(),
+            rom: rom.to_vec(),
+            profile: profile.clone(),
+            spec,
+            config,
+            ticks_per_frame: profile.ticks_per_frame.unwrap_or(DEFAULT_TICKS_PER_FRAME),
+            actions,
+            frame: 0,
+            score: None,
+            done: false,
+        };
+        env.reset(0);
+        Ok(env)
+    }
+    // starts a new episode, returns the first observation
+    pub fn reset(&mut self, seed: u64) -> Vec<u8> {
+        self.emu.reset();
+        self.emu.set_quirks(self.profile.quirks().unwrap_or_default());
+        self.emu.set_seed(seed);
+        self.emu.load(&self.rom).expect("new checked the game fits");
+        self.frame = 0;
+        self.score = self.spec.score.map(|score| self.read(score));
+        self.done = false;
+        self.observe()
+    }
+    // holds an action's keys for frame_skip frames
+    // stepping after the episode is done just returns the last observation again
+    pub fn step(&mut self, action: usize) -> Step {
+        let keys = self.actions.get(action).copied().unwrap_or(0);
+        let mut reward = 0.0;
+        let mut truncated = false;
+        for _ in 0..self.config.frame_skip.max(1) {
+            if self.done {
+                break;
+            }
+            self.emu.set_key_mask(keys);
+            let mut stuck = false;
+            for _ in 0..self.ticks_per_frame {
+                // a game that crashed can't be played any further, same as one that's stuck
+                if self.emu.try_tick().is_err() {
+                    stuck = true;
+                    break;
+                }
+                stuck |= self.stuck();
+            }
+            self.emu.tick_timers();
+            self.frame += 1;
+            if let Some(spec) = self.spec.score {
+                let score = self.read(spec);
+                reward += score as f32 - self.score.unwrap_or(0) as f32;
+                self.score = Some(score);
+            }
+            let lost = self.spec.done.is_some_and(|rule| self.read(rule.value) == rule.equals);
+            truncated = self.config.max_frames.is_some_and(|max| self.frame >= max);
+            self.done = stuck || lost || truncated;
+        }
+        Step {
+            observation: self.observe(),
+            reward,
+            done: self.done,
+            info: StepInfo { frame: self.frame, score: self.score, truncated },
+        }
+    }
+    // number of actions, action 0 presses nothing and the rest press one key each
+    pub fn action_count(&self) -> usize {
+        self.actions.len()
+    }
+    // the keys each action holds, as key masks (bit n = key n)
+    pub fn actions(&self) -> &[u16] {
+        &self.actions
+    }
+    // (rows, columns): (32, 64) for the display, (1, 4096) for memory
+    pub fn observation_shape(&self) -> (usize, usize) {
+        match self.config.observation {
+            ObservationKind::Display => (SCREEN_HEIGHT, SCREEN_WIDTH),
+            ObservationKind::Ram => (1, RAM_SIZE),
+        }
+    }
+    // the emulator, to look at (rendering, debugging) without disturbing the episode
+    pub fn emu(&self) -> &Emu {
+        &self.emu
+    }
+
+    fn observe(&self) -> Vec<u8> {
+        match self.config.observation {
+            ObservationKind::Display => self.emu.get_display().iter().map(|&on| on as u8).collect(),
+            ObservationKind::Ram => self.emu.ram.to_vec(),
+        }
+    }
+
+    fn read(&self, value: RamValue) -> u32 {
+        let start = value.address as usize;
+        let bytes = self.emu.ram.get(start..start + value.length as usize).unwrap_or_default();
+        match value.encoding {
+            Encoding::Bcd => bytes.iter().fold(0, |n, &digit| n * 10 + digit as u32),
+            Encoding::Binary => bytes.iter().fold(0, |n, &byte| n << 8 | byte as u32),
+        }
+    }
+    // the next instruction jumps to itself, nothing but a reset gets the game out of that
+    fn stuck(&self) -> bool {
+        let pc = self.emu.regs.pc as usize;
+        self.emu.ram.get(pc..pc + 2).is_some_and(|op| u16::from_be_bytes([op[0], op[1]]) == 0x1000 | pc as u16)
+    }
+}
+
+impl EnvSpec {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let done = self.done.map(|rule| rule.value);
+        for value in self.score.iter().chain(done.iter()) {
+            if value.length == 0 || value.length > 4 || value.address as usize + value.length as usize > RAM_SIZE {
+                return Err(format!("{} bytes at {:#05x} can't be read as a number", value.length, value.address));
+            }
+        }
+        if let Some(key) = self.keys.iter().flatten().find(|&&key| key as usize >= NUM_KEYS) {
+            return Err(format!("There is no key {:#x}", key));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rom_hash, RomDb, MAX_ROM_SIZE};
+
+    // adds one to V0 and stores it as BCD at 0x300, once a frame at 4 ticks a frame
+    const COUNTER: [u8; 8] = [0x70, 0x01, 0xA3, 0x00, 0xF0, 0x33, 0x12, 0x00];
+    const SCORE: RamValue = RamValue { address: 0x300, length: 3, encoding: Encoding::Bcd };
+
+    fn counter(spec: EnvSpec, config: EnvConfig) -> Environment {
+        let profile = RomProfile { ticks_per_frame: Some(4), env: Some(spec), ..RomProfile::default() };
+        Environment::new(&COUNTER, &profile, config).unwrap()
+    }
+
+    #[test]
+    fn reward_is_how_much_the_score_went_up() {
+        let spec = EnvSpec { score: Some(SCORE), ..EnvSpec::default() };
+        let mut env = counter(spec, EnvConfig::default());
+        let step = env.step(0);
+        assert_eq!(step.reward, 4.0);
+        assert_eq!(step.info, StepInfo { frame: 4, score: Some(4), truncated: false });
+        assert!(!step.done);
+        assert_eq!(env.step(0).reward, 4.0);
+    }
+
+    #[test]
+    fn done_rule_ends_the_episode_on_the_frame_it_matches() {
+        let spec = EnvSpec { score: Some(SCORE), done: Some(DoneRule { value: SCORE, equals: 10 }), keys: None };
+        let mut env = counter(spec, EnvConfig::default());
+        env.step(0);
+        env.step(0);
+        let last = env.step(0);
+        assert!(last.done);
+        assert_eq!(last.info.frame, 10);
+        assert_eq!(last.reward, 2.0);
+        // nothing runs once it's over
+        let after = env.step(0);
+        assert_eq!((after.reward, after.info.frame, after.observation), (0.0, 10, last.observation));
+        env.reset(0);
+        assert!(!env.step(0).done);
+    }
+
+    #[test]
+    fn max_frames_truncates() {
+        let config = EnvConfig { max_frames: Some(6), ..EnvConfig::default() };
+        let mut env = counter(EnvSpec::default(), config);
+        assert!(!env.step(0).done);
+        let step = env.step(0);
+        assert!(step.done && step.info.truncated);
+        assert_eq!(step.info.frame, 6);
+    }
+
+    #[test]
+    fn a_game_that_stops_or_crashes_is_done() {
+        for rom in [[0x12, 0x00], [0xFF, 0xFF]] {
+            let mut env = Environment::new(&rom, &RomProfile::default(), EnvConfig::default()).unwrap();
+            let step = env.step(0);
+            assert!(step.done);
+            assert_eq!(step.info.frame, 1);
+            assert!(!step.info.truncated);
+        }
+    }
+
+    #[test]
+    fn games_that_dont_fit_are_refused() {
+        let profile = RomProfile::default();
+        assert!(Environment::new(&[], &profile, EnvConfig::default()).is_err());
+        assert!(Environment::new(&[0; MAX_ROM_SIZE + 1], &profile, EnvConfig::default()).is_err());
+        assert!(Environment::new(&[0x12, 0x00], &profile, EnvConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn actions_and_observations_follow_the_profile() {
+        let brix = include_bytes!("../../c8games/BRIX");
+        let profile = RomDb::bundled().lookup(rom_hash(brix)).cloned().unwrap();
+        let config = EnvConfig { observation: ObservationKind::Ram, ..EnvConfig::default() };
+        let mut env = Environment::new(brix, &profile, config).unwrap();
+        assert_eq!(env.actions(), [0, 1 << 4, 1 << 6]);
+        assert_eq!(env.observation_shape(), (1, RAM_SIZE));
+        assert_eq!(env.step(1).observation.len(), RAM_SIZE);
+    }
+
+    #[test]
+    fn same_seed_and_actions_play_the_same() {
+        let brix = include_bytes!("../../c8games/BRIX");
+        let profile = RomDb::bundled().lookup(rom_hash(brix)).cloned().unwrap();
+        let play = |seed: u64| {
+            let mut env = Environment::new(brix, &profile, EnvConfig::default()).unwrap();
+            let mut steps = vec![env.reset(seed)];
+            for i in 0..200 {
+                steps.push(env.step(i % 3).observation);
+            }
+            steps
+        };
+        assert_eq!(play(5), play(5));
+        assert_ne!(play(5), play(6));
+    }
+
+    #[test]
+    fn bad_specs_are_refused() {
+        let profile = |spec: EnvSpec| RomProfile { env: Some(spec), ..RomProfile::default() };
+        let wide = RamValue { length: 5, ..SCORE };
+        let spec = EnvSpec { score: Some(wide), ..EnvSpec::default() };
+        assert!(Environment::new(&COUNTER, &profile(spec), EnvConfig::default()).is_err());
+        let spec = EnvSpec { keys: Some(vec![0x10]), ..EnvSpec::default() };
+        assert!(Environment::new(&COUNTER, &profile(spec), EnvConfig::default()).is_err());
+    }
+}
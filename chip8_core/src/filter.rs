@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+
+// an RGBA pixel
+pub type Pixel = [u8; 4];
+
+// how the renderer scales the 64x32 display up, all done on the CPU
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Filter {
+    // plain blocky pixels
+    #[default]
+    Nearest,
+    // EPX / AdvMAME2x, rounds off the corners of diagonal lines
+    Scale2x,
+    // AdvMAME3x, the same idea at three times the size
+    Scale3x,
+    // hq2x-style, Scale2x's corners but blended into their neighbors for softer diagonals
+    Hq2x,
+    // dark gaps between rows like a CRT's scanlines
+    Scanlines,
+    // scanlines plus a red/green/blue aperture grille
+    Crt,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 6] = [Filter::Nearest, Filter::Scale2x, Filter::Scale3x, Filter::Hq2x, Filter::Scanlines, Filter::Crt];
+    // the same names used in config files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Nearest => "nearest",
+            Filter::Scale2x => "scale2x",
+            Filter::Scale3x => "scale3x",
+            Filter::Hq2x => "hq2x",
+            Filter::Scanlines => "scanlines",
+            Filter::Crt => "crt",
+        }
+    }
+
+    pub fn named(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|filter| filter.name() == name)
+    }
+    // upscales a width x height image by the filter's own factor (1, 2 or 3),
+    // returning the new image and its width
+    // the renderer takes it the rest of the way with nearest-neighbor scaling
+    pub fn upscale(&self, src: &[Pixel], width: usize, height: usize) -> (Vec<Pixel>, usize) {
+        match self {
+            Filter::Scale2x => (scale2x(src, width, height, false), width * 2),
+            Filter::Hq2x => (scale2x(src, width, height, true), width * 2),
+            Filter::Scale3x => (scale3x(src, width, height), width * 3),
+            _ => (src.to_vec(), width),
+        }
+    }
+    // effects drawn over the final image, scale is how many output rows/columns
+    // each display pixel covers
+    pub fn post_process(&self, dst: &mut [u8], width: usize, scale: usize) {
+        // there's no room for a gap between rows at 1x
+        if scale < 2 {
+            return;
+        }
+        match self {
+            Filter::Scanlines => scanlines(dst, width, scale),
+            Filter::Crt => {
+                scanlines(dst, width, scale);
+                aperture_grille(dst, width);
+            },
+            _ => (),
+        }
+    }
+}
+
+// neighbor of (x, y), edges repeat the border pixel
+fn at(src: &[Pixel], width: usize, height: usize, x: isize, y: isize) -> Pixel {
+    let x = x.clamp(0, width as isize - 1) as usize;
+    let y = y.clamp(0, height as isize - 1) as usize;
+    src[y * width + x]
+}
+// three parts a to one part b
+fn blend(a: Pixel, b: Pixel) -> Pixel {
+    let mix = |a: u8, b: u8| ((a as u16 * 3 + b as u16) / 4) as u8;
+    [mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2]), 0xFF]
+}
+
+// each pixel P becomes four, a corner takes the color of its two neighbors when they agree
+// (and the other two don't), which turns staircases into smooth diagonals
+//   A      1 2
+// C P B -> 3 4
+//   D
+// with smooth set, corners are only nudged towards the neighbors' color instead
+fn scale2x(src: &[Pixel], width: usize, height: usize, smooth: bool) -> Vec<Pixel> {
+    let mut dst = vec![[0; 4]; src.len() * 4];
+    let out_w = width * 2;
+    for y in 0..height {
+        for x in 0..width {
+            let (xi, yi) = (x as isize, y as isize);
+            let p = src[y * width + x];
+            let a = at(src, width, height, xi, yi - 1);
+            let b = at(src, width, height, xi + 1, yi);
+            let c = at(src, width, height, xi - 1, yi);
+            let d = at(src, width, height, xi, yi + 1);
+            let corner = |take: bool, n: Pixel| match (take, smooth) {
+                (false, _) => p,
+                (true, false) => n,
+                (true, true) => blend(n, p),
+            };
+            let e1 = corner(c == a && c != d && a != b, a);
+            let e2 = corner(a == b && a != c && b != d, b);
+            let e3 = corner(d == c && d != b && c != a, c);
+            let e4 = corner(b == d && b != a && d != c, d);
+            let top = y * 2 * out_w + x * 2;
+            dst[top] = e1;
+            dst[top + 1] = e2;
+            dst[top + out_w] = e3;
+            dst[top + out_w + 1] = e4;
+        }
+    }
+    dst
+}
+
+// AdvMAME3x, the 3x3 version of scale2x's rules
+// A B C
+// D E F
+// G H I
+fn scale3x(src: &[Pixel], width: usize, height: usize) -> Vec<Pixel> {
+    let mut dst = vec![[0; 4]; src.len() * 9];
+    let out_w = width * 3;
+    for y in 0..height {
+        for x in 0..width {
+            let n = |dx: isize, dy: isize| at(src, width, height, x as isize + dx, y as isize + dy);
+            let (a, b, c) = (n(-1, -1), n(0, -1), n(1, -1));
+            let (d, e, f) = (n(-1, 0), n(0, 0), n(1, 0));
+            let (g, h, i) = (n(-1, 1), n(0, 1), n(1, 1));
+            let out = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+            let top = y * 3 * out_w + x * 3;
+            for (k, px) in out.into_iter().enumerate() {
+                dst[top + (k / 3) * out_w + k % 3] = px;
+            }
+        }
+    }
+    dst
+}
+
+// darkens the last row of every display pixel
+fn scanlines(dst: &mut [u8], width: usize, scale: usize) {
+    let pitch = width * 4;
+    for (y, row) in dst.chunks_exact_mut(pitch).enumerate() {
+        if y % scale == scale - 1 {
+            for px in row.chunks_exact_mut(4) {
+                for value in &mut px[..3] {
+                    *value /= 2;
+                }
+            }
+        }
+    }
+}
+
+// every column lets one of red, green or blue through at full strength, like the
+// stripes of phosphor on a Trinitron screen
+fn aperture_grille(dst: &mut [u8], width: usize) {
+    for (i, px) in dst.chunks_exact_mut(4).enumerate() {
+        let stripe = (i % width) % 3;
+        for (channel, value) in px[..3].iter_mut().enumerate() {
+            if channel != stripe {
+                *value = (*value as u16 * 2 / 3) as u8;
+            }
+        }
+    }
+}
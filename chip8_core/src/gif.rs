@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use crate::{Palette, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// GIF frame delays are in hundredths of a second, and most viewers play anything
+// shorter than 2 as if it were 10, so frames are never given less than this
+const MIN_DELAY: u64 = 2;
+// emulated frames per second
+const FPS: u64 = 60;
+
+// records every emulated frame and encodes them as an animated GIF
+// frames are kept as plain on/off pixels (2KB each, about 7MB a minute) and only encoded
+// when the recording is finished
+pub struct GifRecorder {
+    scale: usize,
+    palette: Palette,
+    frames: Vec<Vec<bool>>,
+}
+
+impl GifRecorder {
+    pub fn new(scale: usize, palette: Palette) -> Self {
+        Self { scale: scale.max(1), palette, frames: Vec::new() }
+    }
+    // call once per 60Hz frame with the emulator's display (see Emu::get_display)
+    pub fn capture(&mut self, screen: &[bool]) {
+        self.frames.push(screen.to_vec());
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+    // the recording as a looping GIF
+    // runs of identical frames become one longer frame, and frames too short for a GIF
+    // to show are dropped, without throwing the overall timing off
+    pub fn finish(&self) -> Vec<u8> {
+        let (width, height) = (SCREEN_WIDTH * self.scale, SCREEN_HEIGHT * self.scale);
+        let mut gif = header(width, height, &self.palette);
+        // timestamps are rounded to hundredths from the frame count, so rounding errors
+        // don't pile up over a long recording
+        let centis = |frame: usize| (frame as u64 * 100 + FPS / 2) / FPS;
+        let mut start = 0;
+        while start < self.frames.len() {
+            // how long this picture stays up: until the screen changes, and at least MIN_DELAY
+            let mut end = start + 1;
+            while end < self.frames.len()
+                && (self.frames[end] == self.frames[start] || centis(end) - centis(start) < MIN_DELAY)
+            {
+                end += 1;
+            }
+            // when a short frame got swallowed, show the newest picture for the whole span
+            let shown = if self.frames[end - 1] == self.frames[start] { start } else { end - 1 };
+            let delay = (centis(end) - centis(start)).max(MIN_DELAY);
+            write_frame(&mut gif, &self.frames[shown], self.scale, delay as u16);
+            start = end;
+        }
+        gif.push(0x3B); // trailer
+        gif
+    }
+}
+
+fn header(width: usize, height: usize, palette: &Palette) -> Vec<u8> {
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend_from_slice(&(width as u16).to_le_bytes());
+    gif.extend_from_slice(&(height as u16).to_le_bytes());
+    // global color table of 4 entries (2 bits), all four palette colors
+    gif.extend_from_slice(&[0b1000_0001, 0, 0]);
+    for color in palette.colors {
+        gif.extend_from_slice(&[color.0, color.1, color.2]);
+    }
+    // NETSCAPE2.0 extension, loop forever
+    gif.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+    gif.extend_from_slice(b"NETSCAPE2.0");
+    gif.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+    gif
+}
+
+fn write_frame(gif: &mut Vec<u8>, screen: &[bool], scale: usize, delay: u16) {
+    let (width, height) = (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+    // graphic control extension with the frame's delay
+    gif.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+    gif.extend_from_slice(&delay.to_le_bytes());
+    gif.extend_from_slice(&[0x00, 0x00]);
+    // image descriptor covering the whole canvas, no local color table
+    gif.push(0x2C);
+    gif.extend_from_slice(&[0, 0, 0, 0]);
+    gif.extend_from_slice(&(width as u16).to_le_bytes());
+    gif.extend_from_slice(&(height as u16).to_le_bytes());
+    gif.push(0);
+    // color indexes, scaled up
+    let mut indexes = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = &screen[(y / scale) * SCREEN_WIDTH..(y / scale + 1) * SCREEN_WIDTH];
+        for x in 0..width {
+            indexes.push(row[x / scale] as u8);
+        }
+    }
+    const MIN_CODE_SIZE: u8 = 2;
+    gif.push(MIN_CODE_SIZE);
+    // compressed data goes out in sub-blocks of up to 255 bytes
+    for block in lzw(&indexes, MIN_CODE_SIZE).chunks(255) {
+        gif.push(block.len() as u8);
+        gif.extend_from_slice(block);
+    }
+    gif.push(0);
+}
+
+// GIF's flavour of LZW: codes start one bit wider than the color indexes, grow as the
+// table fills, and the table starts over once it reaches 4096 entries
+fn lzw(data: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end + 1;
+
+    out.write(clear, code_size);
+    let mut prefix: Option<u16> = None;
+    for &byte in data {
+        let current = match prefix {
+            None => {
+                prefix = Some(byte as u16);
+                continue;
+            },
+            Some(current) => current,
+        };
+        if let Some(&code) = table.get(&(current, byte)) {
+            prefix = Some(code);
+            continue;
+        }
+        out.write(current, code_size);
+        if next_code < 4096 {
+            table.insert((current, byte), next_code);
+            next_code += 1;
+            // the decoder widens its codes one code later than we add it
+            if next_code > (1 << code_size) && code_size < 12 {
+                code_size += 1;
+            }
+        } else {
+            // table full, start over
+            out.write(clear, code_size);
+            table.clear();
+            code_size = min_code_size + 1;
+            next_code = end + 1;
+        }
+        prefix = Some(byte as u16);
+    }
+    if let Some(current) = prefix {
+        out.write(current, code_size);
+    }
+    out.write(end, code_size);
+    out.finish()
+}
+
+// packs codes least significant bit first, as GIF wants
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.bits |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a frame as the decoder sees it: how long it stays up and its color indexes
+    struct Frame {
+        delay: u16,
+        indexes: Vec<u8>,
+    }
+
+    // reads back what finish() wrote, checking the layout on the way
+    fn frames(gif: &[u8]) -> Vec<Frame> {
+        assert_eq!(&gif[..6], b"GIF89a");
+        // header, color table and the looping extension
+        let mut pos = 6 + 7 + 4 * 3 + 19;
+        let mut frames = Vec::new();
+        while gif[pos] != 0x3B {
+            assert_eq!(gif[pos..pos + 4], [0x21, 0xF9, 0x04, 0x00]);
+            let delay = u16::from_le_bytes([gif[pos + 4], gif[pos + 5]]);
+            pos += 8;
+            assert_eq!(gif[pos], 0x2C);
+            pos += 10;
+            let min_code_size = gif[pos];
+            pos += 1;
+            let mut data = Vec::new();
+            while gif[pos] != 0 {
+                let len = gif[pos] as usize;
+                data.extend_from_slice(&gif[pos + 1..pos + 1 + len]);
+                pos += 1 + len;
+            }
+            pos += 1;
+            frames.push(Frame { delay, indexes: unlzw(&data, min_code_size) });
+        }
+        assert_eq!(pos, gif.len() - 1);
+        frames
+    }
+
+    // a plain GIF LZW decoder
+    fn unlzw(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let reset = || (0..=end).map(|code| vec![code as u8]).collect::<Vec<_>>();
+        let mut table = reset();
+        let mut code_size = min_code_size + 1;
+        let mut prev: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        let mut bit = 0;
+        loop {
+            let code = (0..code_size as usize).fold(0, |code, i| {
+                let at = bit + i;
+                code | (((data[at / 8] >> (at % 8)) & 1) as usize) << i
+            });
+            bit += code_size as usize;
+            if code == clear {
+                table = reset();
+                code_size = min_code_size + 1;
+                prev = None;
+                continue;
+            }
+            if code == end {
+                break;
+            }
+            let entry = match table.get(code) {
+                Some(entry) => entry.clone(),
+                None => {
+                    let mut entry = prev.clone().unwrap();
+                    entry.push(entry[0]);
+                    entry
+                },
+            };
+            out.extend_from_slice(&entry);
+            if let Some(mut added) = prev {
+                if table.len() < 4096 {
+                    added.push(entry[0]);
+                    table.push(added);
+                }
+            }
+            prev = Some(entry);
+            if table.len() == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn lzw_round_trips_past_a_full_table() {
+        // varied enough to fill the 4096-entry table a few times over
+        let mut seed = 1u32;
+        let data: Vec<u8> = (0..50_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8 & 3
+            })
+            .collect();
+        assert_eq!(unlzw(&lzw(&data, 2), 2), data);
+    }
+
+    #[test]
+    fn a_still_screen_is_one_frame() {
+        let mut recorder = GifRecorder::new(2, Palette::default());
+        let mut screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        screen[SCREEN_WIDTH + 1] = true;
+        for _ in 0..60 {
+            recorder.capture(&screen);
+        }
+        let frames = frames(&recorder.finish());
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].delay, 100);
+        // the lit pixel, scaled up to 2x2
+        let lit: Vec<_> = frames[0].indexes.iter().enumerate().filter(|(_, &i)| i == 1).map(|(n, _)| n).collect();
+        let width = SCREEN_WIDTH * 2;
+        assert_eq!(lit, [2 * width + 2, 2 * width + 3, 3 * width + 2, 3 * width + 3]);
+    }
+
+    #[test]
+    fn flickering_keeps_the_overall_timing() {
+        // a picture change every 60Hz frame is too fast for a GIF
+        let mut recorder = GifRecorder::new(1, Palette::default());
+        for frame in 0..61 {
+            recorder.capture(&[frame % 2 == 0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        }
+        let frames = frames(&recorder.finish());
+        assert!(frames.iter().all(|frame| frame.delay >= MIN_DELAY as u16));
+        assert_eq!(frames.iter().map(|frame| frame.delay as u64).sum::<u64>(), 102);
+    }
+}
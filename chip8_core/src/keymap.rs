@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::NUM_KEYS;
+
+// maps physical keys (or controller buttons) to the 16 chip-8 keys (0x0 to 0xF)
+// keys are named after their position on a US keyboard, using the same names as
+// the browser's KeyboardEvent.code ("KeyQ", "Digit1", "Space", ...)
+// naming positions rather than characters means AZERTY or Dvorak users get the same
+// 4x4 block of keys as QWERTY users without any remapping
+// serialized as a plain table, e.g. in TOML:
+//     KeyQ = 0x4
+//     ArrowUp = 0x5
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Keymap {
+    bindings: BTreeMap<String, u8>,
+}
+
+// the usual layout, the left side of the keyboard stands in for the COSMAC VIP keypad
+// 1 2 3 4      1 2 3 C
+// Q W E R  ->  4 5 6 D
+// A S D F      7 8 9 E
+// Z X C V      A 0 B F
+const DEFAULT_BINDINGS: [(&str, u8); NUM_KEYS] = [
+    ("Digit1", 0x1), ("Digit2", 0x2), ("Digit3", 0x3), ("Digit4", 0xC),
+    ("KeyQ", 0x4),   ("KeyW", 0x5),   ("KeyE", 0x6),   ("KeyR", 0xD),
+    ("KeyA", 0x7),   ("KeyS", 0x8),   ("KeyD", 0x9),   ("KeyF", 0xE),
+    ("KeyZ", 0xA),   ("KeyX", 0x0),   ("KeyC", 0xB),   ("KeyV", 0xF),
+];
+
+// default controller layout, buttons are named like SDL's game controller buttons
+// ("a", "b", "dpup", "leftshoulder", ...) whichever frontend reads them
+// the d-pad acts as the 2/4/6/8 arrow cluster of the VIP keypad with A on the 5 in the middle
+const GAMEPAD_BINDINGS: [(&str, u8); 6] = [
+    ("dpup", 0x2), ("dpdown", 0x8), ("dpleft", 0x4), ("dpright", 0x6),
+    ("a", 0x5),    ("b", 0x0),
+];
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self::empty();
+        for (code, key) in DEFAULT_BINDINGS {
+            keymap.bind(code, key);
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    // keymap with nothing bound, for building one from scratch
+    pub fn empty() -> Self {
+        Self { bindings: BTreeMap::new() }
+    }
+    // default keymap for a controller
+    pub fn gamepad() -> Self {
+        let mut keymap = Self::empty();
+        for (button, key) in GAMEPAD_BINDINGS {
+            keymap.bind(button, key);
+        }
+        keymap
+    }
+    // binds a physical key to a chip-8 key, replacing whatever it was bound to before
+    // several physical keys may share one chip-8 key
+    pub fn bind(&mut self, code: &str, key: u8) {
+        self.bindings.insert(code.to_string(), key);
+    }
+
+    pub fn unbind(&mut self, code: &str) {
+        self.bindings.remove(code);
+    }
+    // chip-8 key bound to a physical key, if any
+    // out of range values from a hand-written file are treated as unbound
+    pub fn lookup(&self, code: &str) -> Option<usize> {
+        match self.bindings.get(code) {
+            Some(&key) if (key as usize) < NUM_KEYS => Some(key as usize),
+            _ => None,
+        }
+    }
+    // layers user overrides on top of this keymap
+    // bindings in the override win, everything else is kept
+    pub fn merge(&mut self, overrides: &Keymap) {
+        for (code, &key) in &overrides.bindings {
+            self.bindings.insert(code.clone(), key);
+        }
+    }
+    // every (physical key, chip-8 key) pair, sorted by physical key name
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.bindings.iter().map(|(code, &key)| (code.as_str(), key))
+    }
+    // checks a keymap loaded from a user file, returns the first bad binding
+    pub fn validate(&self) -> Result<(), String> {
+        for (code, &key) in &self.bindings {
+            if key as usize >= NUM_KEYS {
+                return Err(format!("{} is bound to {:#X}, but chip-8 keys only go up to 0xF", code, key));
+            }
+        }
+        Ok(())
+    }
+}
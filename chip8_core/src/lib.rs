@@ -1,4 +1,41 @@
 use rand::random;
+use std::fmt;
+
+mod analyze;
+mod batch;
+mod cheats;
+mod debug;
+mod env;
+mod filter;
+mod gif;
+mod keymap;
+mod movie;
+mod palette;
+mod persistence;
+mod png;
+mod render;
+mod quirks;
+mod romdb;
+mod state;
+mod video;
+pub use analyze::{analyze, Analysis};
+pub use batch::{BatchEmu, Ending};
+pub use cheats::{parse_address, parse_bytes, Cheat, Cheats, Compare, MemorySearch};
+pub use debug::{CpuState, Debugger};
+pub use env::{DoneRule, Encoding, EnvConfig, EnvSpec, Environment, ObservationKind, RamValue, Step, StepInfo};
+pub use filter::Filter;
+pub use gif::GifRecorder;
+pub use keymap::Keymap;
+pub use movie::{Desync, Movie, CHECKSUM_INTERVAL};
+pub use palette::{Palette, Rgb};
+pub use persistence::{DisplayMode, Persistence, DEFAULT_DECAY};
+pub use png::{encode_png, screenshot};
+pub use render::Renderer;
+pub use quirks::{Platform, Quirks};
+pub use romdb::{rom_hash, RomDb, RomProfile};
+pub use state::SAVE_STATE_SIZE;
+pub use video::{FrameStream, StreamFormat};
+
 // chip-8 uses a 64x32 monochromatic display
 // public for allowing access to the frontend
 pub const SCREEN_WIDTH: usize = 64;
@@ -33,69 +70,188 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
-const RAM_SIZE: usize = 4096; // max memory allowed in chip-8
+pub const RAM_SIZE: usize = 4096; // max memory allowed in chip-8
 const NUM_REGS: usize = 16;
 const STACK_SIZE: usize = 16;
-const NUM_KEYS: usize = 16;
+// public so frontends know how many keys they can map to
+pub const NUM_KEYS: usize = 16;
 const START_ADDR: u16 = 0x200; // rams starts reading at address 0x200
+pub const MAX_ROM_SIZE: usize = RAM_SIZE - START_ADDR as usize; // biggest game load() can take
 
-pub struct Emu {
+// the CPU's registers and the bits of state that go with them
+// kept apart from memory and the display so BatchEmu can keep many of them side by side
+#[derive(Clone, Copy)]
+pub(crate) struct Regs {
     pc: u16, // program counter, keeps tracks which instruction it currently is executing; increments as the game runs
-    ram: [u8; RAM_SIZE], // ram for our emulator
-    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT], // since its a monochromatic display, we can store each pixels' color with a boolean
     v_reg: [u8; NUM_REGS], // registers that chip-8 uses, going from V0 to VF (16 in total), VF doubles as the flag register
     i_reg: u16, // for indexing the ram
     sp: u16, // stack pointer, keeps track of the top of the stack
     stack: [u16; STACK_SIZE], // stack for the cpu to read/write to
-    keys: [bool; NUM_KEYS],
     dt: u8, // delay timer, typical timer, performs action if it hits 0
     st: u8, // sound timer, emits sound when it hits 0
+    // FX0A state that has to survive between ticks when waiting for a key release
+    key_wait: bool, // true while an FX0A is in progress
+    key_wait_held: [bool; NUM_KEYS], // keys already held when the wait began, ignored until released
+    key_wait_latch: Option<u8>, // key that was pressed during the wait, done once it is released
+    keys_tested: u16, // bit n is set once the game has checked key n with EX9E/EXA1
+    rng: u64, // random number generator state, see next_random
 }
 
-impl Emu {
-    pub fn new() -> Self {
+#[derive(Clone)]
+pub struct Emu {
+    regs: Regs,
+    ram: [u8; RAM_SIZE], // ram for our emulator
+    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT], // since its a monochromatic display, we can store each pixels' color with a boolean
+    keys: [bool; NUM_KEYS],
+    quirks: Quirks,
+    rom_hash: u64, // content hash of the loaded game, for looking it up in a RomDb
+    rom_len: usize, // size of the loaded game in bytes
+    seed: u64, // what the random number generator started from, replaying a game needs the same one
+}
+
+// everything an instruction can touch, borrowed from an Emu or from one slot of a BatchEmu
+// so both run the exact same decode/execute code
+pub(crate) struct Core<'a> {
+    regs: &'a mut Regs,
+    ram: &'a mut [u8; RAM_SIZE],
+    screen: &'a mut [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+    keys: &'a [bool; NUM_KEYS],
+    quirks: Quirks,
+}
+
+// something a game did that no chip-8 could carry on from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    // more than 16 nested subroutine calls
+    StackOverflow,
+    // a return with no subroutine to return from
+    StackUnderflow,
+    // an instruction or I pointed past the end of the 4KB
+    OutOfMemory(usize),
+    // EX9E/EXA1 checked a key above F
+    BadKey(u8),
+    UnknownOpcode(u16),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackOverflow => write!(f, "Stack overflow"),
+            Fault::StackUnderflow => write!(f, "Return with an empty stack"),
+            Fault::OutOfMemory(addr) => write!(f, "Memory access out of range: {:#x}", addr),
+            Fault::BadKey(key) => write!(f, "There is no key {:#x}", key),
+            Fault::UnknownOpcode(op) => write!(f, "Unimplemented opcode: {:#06x}", op),
+        }
+    }
+}
+
+impl Regs {
+    pub(crate) fn new(seed: u64) -> Self {
         // initalizes all values and arrays to zero (except the program counter)
-        let mut new_emu = Self {
+        Self {
             pc: START_ADDR,
-            ram: [0; RAM_SIZE],
-            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
             v_reg: [0; NUM_REGS],
             i_reg: 0,
             sp: 0,
             stack: [0; STACK_SIZE],
-            keys: [false; NUM_KEYS],
             dt: 0,
             st: 0,
-        };
-        // we will use the ram before the start address (0x200) for our sprites as this would be unused in our emulator
-        // (better efficiency)
-        new_emu.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+            key_wait: false,
+            key_wait_held: [false; NUM_KEYS],
+            key_wait_latch: None,
+            keys_tested: 0,
+            rng: seed,
+        }
+    }
+    // each cpu cycle, each timer (delay and sound) will decrease once every frame
+    pub(crate) fn tick_timers(&mut self) {
+        // each timer will decrease unless they are 0
+        // in these case they will remain unchanged until changed by the game manually
+        if self.dt > 0 {
+            self.dt -= 1;
+        }
+        // when sound timer reaches 0 it will emit a beep
+        if self.st > 0 {
+            if self.st == 1 {
+                // emit a beep
+            }
+            self.st -= 1;
+        }
+    }
+}
 
-        new_emu
+// memory as it is at power on, all zeros apart from the font
+// we will use the ram before the start address (0x200) for our sprites as this would be unused in our emulator
+// (better efficiency)
+pub(crate) fn blank_ram() -> [u8; RAM_SIZE] {
+    let mut ram = [0; RAM_SIZE];
+    ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+    ram
+}
+
+// the one place that decides whether a game can be loaded, Emu::load and BatchEmu::new both ask it
+pub(crate) fn check_rom(data: &[u8]) -> Result<(), String> {
+    if data.is_empty() {
+        return Err("The game is empty".to_string());
+    }
+    if data.len() > MAX_ROM_SIZE {
+        return Err(format!("The game is {} bytes, only {} fit in memory", data.len(), MAX_ROM_SIZE));
+    }
+    Ok(())
+}
+
+// a copy of the whole emulator at one moment, see Emu::save_state
+// to_bytes/from_bytes turn it into a fixed-size blob for writing to disk
+#[derive(Clone)]
+pub struct SaveState(Box<Emu>);
+
+impl Default for Emu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emu {
+    pub fn new() -> Self {
+        // a different game every run unless the frontend picks a seed
+        let seed = random();
+        Self {
+            regs: Regs::new(seed),
+            ram: blank_ram(),
+            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            keys: [false; NUM_KEYS],
+            quirks: Quirks::default(),
+            rom_hash: 0,
+            rom_len: 0,
+            seed,
+        }
     }
     // resets emulator without needing to create a new object
     pub fn reset(&mut self) {
-        self.pc = START_ADDR;
-        self.ram = [0; RAM_SIZE];
+        self.regs = Regs::new(self.seed);
+        self.ram = blank_ram();
         self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
-        self.v_reg = [0; NUM_REGS];
-        self.i_reg = 0;
-        self.sp = 0;
-        self.stack = [0; STACK_SIZE];
         self.keys = [false; NUM_KEYS];
-        self.dt = 0;
-        self.st =  0;
-        self.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        self.rom_hash = 0;
+        self.rom_len = 0;
     }
-    // basic push and pop functions for our stack
-    fn push(&mut self, val: u16) { 
-        self.stack[self.sp as usize] = val;
-        self.sp += 1;
+    // quirks are configuration rather than state, so reset() leaves them alone
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
     }
-    // of course, returns the popped value
-    fn pop(&mut self) ->  u16 {
-        self.sp -= 1;
-        self.stack[self.sp as usize]
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+    // restarts the random number generator from a known point, so the same seed and the same
+    // inputs play out exactly the same way (reset() goes back to the start of the sequence)
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.regs.rng = seed;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
     }
     /* basic loop in our emulator (from the book):
         1. Fetch the value from our game (loaded into RAM) at the memory address stored in our Program Counter.
@@ -104,14 +260,165 @@ impl Emu {
         4. Move the PC to the next instruction and repeat
     */
     pub fn tick(&mut self) {
+        if let Err(fault) = self.try_tick() {
+            panic!("{}", fault);
+        }
+    }
+    // like tick, but a game doing something impossible comes back as an error instead of a panic
+    pub fn try_tick(&mut self) -> Result<(), Fault> {
+        self.core().tick()
+    }
+
+    fn core(&mut self) -> Core<'_> {
+        Core {
+            regs: &mut self.regs,
+            ram: &mut self.ram,
+            screen: &mut self.screen,
+            keys: &self.keys,
+            quirks: self.quirks,
+        }
+    }
+    // each cpu cycle, each timer (delay and sound) will decrease once every frame
+    pub fn tick_timers(&mut self) {
+        self.regs.tick_timers();
+    }
+    // public function that gives a pointer to the display for the frontend
+    pub fn get_display(&self) -> &[bool] {
+        &self.screen
+    }
+    // whether the beeper is on, frontends poll it once a frame
+    pub fn sound_active(&self) -> bool {
+        self.regs.st > 0
+    }
+    // handles user key presses
+    pub fn keypress(&mut self, idx: usize, pressed: bool) {
+        self.keys[idx] = pressed;
+    }
+    // every key's state at once, bit n = key n
+    // handy for recording input once per frame
+    pub fn key_mask(&self) -> u16 {
+        self.keys.iter().enumerate().fold(0, |mask, (i, &held)| mask | (held as u16) << i)
+    }
+    // sets every key at once, the same as calling keypress for each of them
+    pub fn set_key_mask(&mut self, mask: u16) {
+        for (i, key) in self.keys.iter_mut().enumerate() {
+            *key = mask & (1 << i) != 0;
+        }
+    }
+    // keys the game has checked with EX9E/EXA1 since the last reset, as a bitmask (bit n = key n)
+    // lets frontends point out which keys a game actually uses
+    pub fn tested_keys(&self) -> u16 {
+        self.regs.keys_tested
+    }
+    // copy data into ram, refusing games that are empty or don't fit
+    pub fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        check_rom(data)?;
+        let start = START_ADDR as usize;
+        let end = (START_ADDR as usize) + data.len();
+        self.ram[start..end].copy_from_slice(data);
+        self.rom_hash = rom_hash(data);
+        self.rom_len = data.len();
+        Ok(())
+    }
+    // the game as it was loaded, starting at 0x200
+    // (reads current ram, so self-modifying games show their changes)
+    pub fn program(&self) -> &[u8] {
+        let start = START_ADDR as usize;
+        &self.ram[start..start + self.rom_len]
+    }
+    // swaps in a new build of the game, for hot reloading while writing one
+    // with keep_state the registers, timers, display and the memory outside the new program
+    // carry over, so a change to the code can be tried without playing back to the same spot
+    // (the game gets no warning, if its code moved around the PC may land somewhere odd)
+    // a game load() would refuse leaves the emulator as it was
+    pub fn reload(&mut self, data: &[u8], keep_state: bool) -> Result<(), String> {
+        let old = self.clone();
+        self.reset();
+        if let Err(e) = self.load(data) {
+            *self = old;
+            return Err(e);
+        }
+        if keep_state {
+            let (start, end) = (START_ADDR as usize, START_ADDR as usize + data.len());
+            self.ram[..start].copy_from_slice(&old.ram[..start]);
+            self.ram[end..].copy_from_slice(&old.ram[end..]);
+            self.regs = old.regs;
+            self.screen = old.screen;
+            self.keys = old.keys;
+        }
+        Ok(())
+    }
+    // hash of the last game loaded, 0 if nothing has been loaded since the last reset
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+    // snapshots the emulator, quirks and game included, so it can be put back exactly as it was
+    // cheap enough (a few KB) to take one every frame, which is what netplay rollback does
+    pub fn save_state(&self) -> SaveState {
+        SaveState(Box::new(self.clone()))
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.clone_from(&state.0);
+    }
+    // FNV-1a over everything that decides what the emulator does next
+    // two emulators with the same hash will keep doing the same thing given the same input,
+    // which is how movies and netplay notice they have drifted apart
+    pub fn state_hash(&self) -> u64 {
+        let mut hash = romdb::Fnv::default();
+        hash.write(&self.regs.pc.to_le_bytes());
+        hash.write(&self.ram);
+        hash.write(&self.screen.map(|px| px as u8));
+        hash.write(&self.regs.v_reg);
+        hash.write(&self.regs.i_reg.to_le_bytes());
+        hash.write(&self.regs.sp.to_le_bytes());
+        for val in self.regs.stack {
+            hash.write(&val.to_le_bytes());
+        }
+        hash.write(&self.key_mask().to_le_bytes());
+        hash.write(&[self.regs.dt, self.regs.st, self.regs.key_wait as u8, self.regs.key_wait_latch.map_or(0xFF, |k| k)]);
+        hash.write(&self.regs.key_wait_held.map(|held| held as u8));
+        hash.write(&self.regs.rng.to_le_bytes());
+        hash.finish()
+    }
+}
+
+impl Core<'_> {
+    // one instruction, see Emu::tick
+    pub(crate) fn tick(&mut self) -> Result<(), Fault> {
         // fetch
-        let op = self.fetch();
+        let op = self.fetch()?;
         // decode
         // execute
-        self.execute(op);
+        self.execute(op)
+    }
+    // splitmix64, small and fast, and any seed (0 included) gives a good sequence
+    fn next_random(&mut self) -> u64 {
+        self.regs.rng = self.regs.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.regs.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    // basic push and pop functions for our stack
+    fn push(&mut self, val: u16) -> Result<(), Fault> {
+        if self.regs.sp as usize >= STACK_SIZE {
+            return Err(Fault::StackOverflow);
+        }
+        self.regs.stack[self.regs.sp as usize] = val;
+        self.regs.sp += 1;
+        Ok(())
+    }
+    // of course, returns the popped value
+    fn pop(&mut self) -> Result<u16, Fault> {
+        if self.regs.sp == 0 {
+            return Err(Fault::StackUnderflow);
+        }
+        self.regs.sp -= 1;
+        Ok(self.regs.stack[self.regs.sp as usize])
     }
     // decodes the given opcode and executes it
-    fn execute(&mut self, op: u16) {
+    fn execute(&mut self, op: u16) -> Result<(), Fault> {
         // seperates the op code into four hex digits
         let digit1 = (op & 0xF000) >> 12;
         let digit2 = (op & 0x0F00) >> 8;
@@ -121,31 +428,31 @@ impl Emu {
         match (digit1, digit2, digit3, digit4) {
             // NOP instruction
             // moves to the next opcode (needed for timing or aligment purposes)
-            (0, 0, 0, 0) => return,
+            (0, 0, 0, 0) => (),
             // Clear screen instruction
             (0, 0, 0xE, 0) => {
-                self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+                *self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
             },
             // Return from subroutine
             // gets the last address pushed in the stack to continue from after a subroutine
             (0, 0, 0xE, 0xE) => {
-                let ret_addr = self.pop();
-                self.pc = ret_addr;
+                let ret_addr = self.pop()?;
+                self.regs.pc = ret_addr;
             },
             // Jump
             // simply moves the pointer counter to the given address
             // opcode beings with 0x1, and the next three digits (nnn) are any three digits
             (1, _, _, _) => {
                 let nnn = op & 0xFFF;
-                self.pc = nnn;
+                self.regs.pc = nnn;
             },
             // Call subroutine
             // opposite function of Return from subroutine
             // add the current pointer counter to the stack and jump to the given address (nnn)
             (2, _, _, _) => {
                 let nnn = op & 0xFFF;
-                self.push(self.pc);
-                self.pc = nnn;
+                self.push(self.regs.pc)?;
+                self.regs.pc = nnn;
             },
             // Skip next if VX == NN
             // basically acts as an if-else block
@@ -157,8 +464,8 @@ impl Emu {
                 let x = digit2 as usize;
                 let nn = (op & 0xFF) as u8;
                 // uses one of the V registers
-                if self.v_reg[x] == nn {
-                    self.pc += 2;
+                if self.regs.v_reg[x] == nn {
+                    self.regs.pc += 2;
                 }
             },
             // Skip next if VX != NN
@@ -167,8 +474,8 @@ impl Emu {
                 let x = digit2 as usize;
                 let nn = (op & 0xFF) as u8;
                 // uses one of the V registers
-                if self.v_reg[x] != nn {
-                    self.pc += 2;
+                if self.regs.v_reg[x] != nn {
+                    self.regs.pc += 2;
                 }
             },
             // Skip next if VX == VY
@@ -179,8 +486,8 @@ impl Emu {
             (5, _, _, 0) => {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
-                if self.v_reg[x] == self.v_reg[y] {
-                    self.pc += 2;
+                if self.regs.v_reg[x] == self.regs.v_reg[y] {
+                    self.regs.pc += 2;
                 }
             },
             // VX = NN
@@ -188,7 +495,7 @@ impl Emu {
             (6, _, _, _) => {
                 let x = digit2 as usize;
                 let nn = (op & 0xFF) as u8;
-                self.v_reg[x] = nn;
+                self.regs.v_reg[x] = nn;
             }
             // VX += NN
             // adds the value given to the VX register
@@ -197,33 +504,42 @@ impl Emu {
             (7, _, _, _) => {
                 let x = digit2 as usize;
                 let nn = (op & 0xFF) as u8;
-                self.v_reg[x] = self.v_reg[x].wrapping_add(nn);
+                self.regs.v_reg[x] = self.regs.v_reg[x].wrapping_add(nn);
             },
             // VX = VY
             // like the VX = NN operation, but the source value is from the VY register
             (8, _, _, 0) => {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
-                self.v_reg[x] = self.v_reg[y];
+                self.regs.v_reg[x] = self.regs.v_reg[y];
             },
             // VX |= VY
             // applies OR operator to VX register and VY register
             (8, _, _, 1) => {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
-                self.v_reg[x] |= self.v_reg[y];
+                self.regs.v_reg[x] |= self.regs.v_reg[y];
+                if self.quirks.vf_reset {
+                    self.regs.v_reg[0xF] = 0;
+                }
             },
             // VX &= VY
             (8, _, _, 2) => {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
-                self.v_reg[x] &= self.v_reg[y];
+                self.regs.v_reg[x] &= self.regs.v_reg[y];
+                if self.quirks.vf_reset {
+                    self.regs.v_reg[0xF] = 0;
+                }
             },
             // VX ^= VY
             (8, _, _, 3) => {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
-                self.v_reg[x] ^= self.v_reg[y];
+                self.regs.v_reg[x] ^= self.regs.v_reg[y];
+                if self.quirks.vf_reset {
+                    self.regs.v_reg[0xF] = 0;
+                }
             },
             // VX += VY
             // VX register becomes VX plus VY 
@@ -231,12 +547,12 @@ impl Emu {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
                 // overflowing_add retuns a tuple, which contains the wrapped sum, and a boolean that tells us if an overflow occured               
-                let (new_vx, carry) = self.v_reg[x].overflowing_add(self.v_reg[y]);
+                let (new_vx, carry) = self.regs.v_reg[x].overflowing_add(self.regs.v_reg[y]);
                 // VF needs to be set to 1 if an overflow happened in the sum, 0 in case it didn't
                 let new_vf = if carry { 1 } else { 0 };
                 // sets the new values of VF and VX
-                self.v_reg[x] = new_vx;
-                self.v_reg[0xF] = new_vf;
+                self.regs.v_reg[x] = new_vx;
+                self.regs.v_reg[0xF] = new_vf;
             },
             // VX -= VY 
             // same operation as previous but with a substraction
@@ -245,21 +561,25 @@ impl Emu {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
 
-                let (new_vx, borrow) = self.v_reg[x].overflowing_sub(self.v_reg[y]);
+                let (new_vx, borrow) = self.regs.v_reg[x].overflowing_sub(self.regs.v_reg[y]);
 
                 let new_vf = if borrow { 0 } else { 1 };
 
-                self.v_reg[x] = new_vx;
-                self.v_reg[0xF] = new_vf;
+                self.regs.v_reg[x] = new_vx;
+                self.regs.v_reg[0xF] = new_vf;
             },
             // VX >>= 1
             // performs one right shift on the value in VX
             (8, _, _, 6) => {
                 let x = digit2 as usize;
+                // the original interpreter shifted VY and stored the result in VX
+                if self.quirks.shift_vy {
+                    self.regs.v_reg[x] = self.regs.v_reg[digit3 as usize];
+                }
                 // gets the dropped off bit and will store it in VF
-                let lsb = self.v_reg[x] & 1;
-                self.v_reg[x] >>= 1;
-                self.v_reg[0xF] = lsb;
+                let lsb = self.regs.v_reg[x] & 1;
+                self.regs.v_reg[x] >>= 1;
+                self.regs.v_reg[0xF] = lsb;
             },
             // VX = VY - VX
             // wors the same way as VX -= VY, but with operands in the opposite direction
@@ -267,28 +587,31 @@ impl Emu {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
 
-                let (new_vx, borrow) = self.v_reg[y].overflowing_sub(self.v_reg[x]);
+                let (new_vx, borrow) = self.regs.v_reg[y].overflowing_sub(self.regs.v_reg[x]);
 
                 let new_vf = if borrow { 0 } else { 1 };
 
-                self.v_reg[x] = new_vx;
-                self.v_reg[0xF] = new_vf;
+                self.regs.v_reg[x] = new_vx;
+                self.regs.v_reg[0xF] = new_vf;
             },
             // VX <<= 1
             // similar to the right shift operation, but that overflowed value is stored in VF
             (8, _, _, 0xE) => {
                 let x = digit2 as usize;
-                
-                let msb = (self.v_reg[x] >> 7) & 1;
-                self.v_reg[x] <<= 1;
-                self.v_reg[0xF] = msb;
+                if self.quirks.shift_vy {
+                    self.regs.v_reg[x] = self.regs.v_reg[digit3 as usize];
+                }
+
+                let msb = (self.regs.v_reg[x] >> 7) & 1;
+                self.regs.v_reg[x] <<= 1;
+                self.regs.v_reg[0xF] = msb;
             },
             // Skip if VX != VY
             (9, _, _, 0) => {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
-                if self.v_reg[x] != self.v_reg[y] {
-                    self.pc += 2;
+                if self.regs.v_reg[x] != self.regs.v_reg[y] {
+                    self.regs.pc += 2;
                 }
             },
             // I = NNN
@@ -296,26 +619,27 @@ impl Emu {
             // simply we are setting it as 0xNNN
             (0xA, _, _, _) => {
                 let nnn = op & 0xFFF;
-                self.i_reg = nnn;
+                self.regs.i_reg = nnn;
             },
             // Jump to V0 + NNN
             // utilizes the first V register (V0)
             // moves the pointer counter to the sum of the value stored in V0 and the raw value 0xNNN
+            // (SUPER-CHIP reads this as BXNN, jumping to VX + XNN)
             (0xB, _, _, _) => {
                 let nnn = op & 0xFFF;
-                self.pc = (self.v_reg[0] as u16) + nnn;
+                let reg = if self.quirks.jump_vx { digit2 as usize } else { 0 };
+                self.regs.pc = (self.regs.v_reg[reg] as u16) + nnn;
             },
             // VX = rand() & NN
             // chip8 rng operation
             // random number is AND with two values in the opcode (NN)
             // sets that to the VX register
-            // will be using rust's rand library
+            // uses the emulator's own seeded generator, so a recorded game can be replayed
             (0xC, _, _, _) => {
                 let x = digit2 as usize;
                 let nn = (op & 0xFF) as u8;
-                // setting the random variable as u8 is necessary for random() to know which type it is supposed to generate
-                let rng: u8 = random();
-                self.v_reg[x] = rng & nn;
+                let rng = self.next_random() as u8;
+                self.regs.v_reg[x] = rng & nn;
             },
             // Draw sprite
             // most complicated opcode
@@ -330,8 +654,8 @@ impl Emu {
             // any pixel flipped from white to black or viceverse, VF is set and cleared otherwise
             (0xD, _, _, _) => {
                 // getting coordinates (x,y) from the V register for our sprite
-                let x_coord = self.v_reg[digit2 as usize] as u16;
-                let y_coord = self.v_reg[digit3 as usize] as u16;
+                let x_coord = self.regs.v_reg[digit2 as usize] as u16;
+                let y_coord = self.regs.v_reg[digit3 as usize] as u16;
                 // the last digit determines the sprite's height
                 let num_rows = digit4;
                 // keep track if any pixels were flipped
@@ -339,8 +663,8 @@ impl Emu {
                 // iteration over each row of our sprite
                 for y_line in 0..num_rows {
                     // get memory address from where the row's data is stored
-                    let addr = self.i_reg + y_line as u16;
-                    let pixels = self.ram[addr as usize];
+                    let addr = self.regs.i_reg as usize + y_line as usize;
+                    let pixels = self.read(addr)?;
                     // iterate over each column in the row
                     for x_line in 0..8 {
                         // use mask to fetch current pixel's bit
@@ -359,9 +683,9 @@ impl Emu {
                 }
                 // set the VF register
                 if flipped {
-                    self.v_reg[0xF] = 1;
+                    self.regs.v_reg[0xF] = 1;
                 } else {
-                    self.v_reg[0xF] = 0;
+                    self.regs.v_reg[0xF] = 0;
                 }
             },
             // Skip if key pressed
@@ -371,27 +695,29 @@ impl Emu {
             // if so, skip to the next instruction
             (0xE, _, 9, 0xE) => {
                 let x = digit2 as usize;
-                let vx = self.v_reg[x];
-                let key = self.keys[vx as usize];
+                let vx = self.regs.v_reg[x];
+                let key = *self.keys.get(vx as usize).ok_or(Fault::BadKey(vx))?;
+                self.regs.keys_tested |= 1 << vx;
                 if key {
-                    self.pc += 2;
+                    self.regs.pc += 2;
                 }
             },
             // Skip if key not pressed
             // same as previous one but as an inequality as to whether the key was pressed
             (0xE, _, 0xA, 1) => {
                 let x = digit2 as usize;
-                let vx = self.v_reg[x];
-                let key = self.keys[vx as usize];
+                let vx = self.regs.v_reg[x];
+                let key = *self.keys.get(vx as usize).ok_or(Fault::BadKey(vx))?;
+                self.regs.keys_tested |= 1 << vx;
                 if !key {
-                    self.pc += 2;
+                    self.regs.pc += 2;
                 }
             },
             // VX = DT
             // stores the delay timer in VX register
             (0xF, _, 0, 7) => {
                 let x = digit2 as usize;
-                self.v_reg[x] = self.dt;
+                self.regs.v_reg[x] = self.regs.dt;
             },
             // Wait for key press
             // instruction is blocking
@@ -399,39 +725,48 @@ impl Emu {
             // loops endlessly unitl something in the keys array turns true
             (0xF, _, 0, 0xa) => {
                 let x = digit2 as usize;
+                if self.quirks.wait_key_release {
+                    // original behaviour, a key has to go down and back up
+                    if let Some(key) = self.wait_key_release() {
+                        self.regs.v_reg[x] = key;
+                    } else {
+                        self.regs.pc -= 2;
+                    }
+                    return Ok(());
+                }
                 let mut pressed = false;
                 // cycles through the keys array
                 for i in 0..self.keys.len() {
                     if self.keys[i] {
-                        self.v_reg[x] = i as u8;
+                        self.regs.v_reg[x] = i as u8;
                         pressed = true;
                         break;
                     }
                 }
                 // redo opcode if key wasn't pressed
                 if !pressed {
-                    self.pc -= 2
+                    self.regs.pc -= 2
                 }
             },
             // DT = VX
             // overwrites the delay timer to whatever the value in VX register is
             (0xF, _, 1, 5) => {
                 let x = digit2 as usize;
-                self.dt = self.v_reg[x];
+                self.regs.dt = self.regs.v_reg[x];
             },
             // ST = VX 
             // same as previous one, but overwrites instead the sound timer
             (0xF, _, 1, 8) => {
                 let x = digit2 as usize;
-                self.st = self.v_reg[x];
+                self.regs.st = self.regs.v_reg[x];
             },
             // I += VX
             // increments the I register to the value in VX
             // roll back to 0 in case of an overflow, so we are wrapping_add
             (0xF, _, 1, 0xE) => {
                 let x = digit2 as usize;
-                let vx = self.v_reg[x] as u16;
-                self.i_reg = self.i_reg.wrapping_add(vx);
+                let vx = self.regs.v_reg[x] as u16;
+                self.regs.i_reg = self.regs.i_reg.wrapping_add(vx);
             },
             // Set I to Font Address
             // using the first 512 values in the ram (was set as the font data)
@@ -441,8 +776,8 @@ impl Emu {
             // .. 5 bytes, we can simply multiply the value times 5
             (0xF, _, 2, 9) => {
                 let x = digit2 as usize;
-                let c = self.v_reg[x] as u16;
-                self.i_reg = c * 5;
+                let c = self.regs.v_reg[x] as u16;
+                self.regs.i_reg = c * 5;
             },
             // I = BCD of VX
             // stores the binary-coded decimal of number stored in VX into the I register
@@ -455,7 +790,7 @@ impl Emu {
             (0xF, _, 3, 3) => {
                 let x = digit2 as usize;
                 // converted as float so that we can use division and modulo to get decimal digits
-                let vx = self.v_reg[x] as f32;
+                let vx = self.regs.v_reg[x] as f32;
                 // fetch hunderds by dividing by 100 and tossing the decimals
                 let hundreds = (vx / 100.0).floor() as u8;
                 // fetch tenths by dividing by 10 and tossing the ones and the decimal
@@ -463,9 +798,10 @@ impl Emu {
                 // fetch ones by tossing the hunders and the tens
                 let ones = (vx % 10.0) as u8;
 
-                self.ram[self.i_reg as usize] = hundreds;
-                self.ram[(self.i_reg + 1) as usize] = tens;
-                self.ram[(self.i_reg + 2) as usize] = ones;
+                let i = self.regs.i_reg as usize;
+                self.write(i, hundreds)?;
+                self.write(i + 1, tens)?;
+                self.write(i + 2, ones)?;
             },
             // Store V0 - VX into I
             // final two instructions populate the V register V0 through VX (incl.) ..
@@ -473,63 +809,170 @@ impl Emu {
             // stores the values into ram
             (0xF, _, 5, 5) => {
                 let x = digit2 as usize;
-                let i = self.i_reg as usize;
+                let i = self.regs.i_reg as usize;
                 for idx in 0..=x {
-                    self.ram[i + idx] = self.v_reg[idx];
+                    self.write(i + idx, self.regs.v_reg[idx])?;
+                }
+                if self.quirks.load_store_increment_i {
+                    self.regs.i_reg += x as u16 + 1;
                 }
             }
             // Load I into V0 - VX
             // opposite of previous instruction
             (0xF, _, 6, 5) => {
                 let x = digit2 as usize;
-                let i = self.i_reg as usize;
+                let i = self.regs.i_reg as usize;
                 for idx in 0..=x {
-                    self.v_reg[idx] = self.ram[i + idx];
+                    self.regs.v_reg[idx] = self.read(i + idx)?;
+                }
+                if self.quirks.load_store_increment_i {
+                    self.regs.i_reg += x as u16 + 1;
                 }
             },
             // match case for everything else
             // would probably never reach here, but rust demands it
-            (_, _, _, _) => unimplemented!("Unimplemented opcode: {}", op),
+            (_, _, _, _) => return Err(Fault::UnknownOpcode(op)),
+        }
+        Ok(())
+    }
+    // one step of FX0A with the wait_key_release quirk, runs once per tick while the wait lasts
+    // returns the key once it has been pressed and released
+    fn wait_key_release(&mut self) -> Option<u8> {
+        // first time through, remember what is already held from before
+        // so a key left down from a previous prompt can't answer this one
+        if !self.regs.key_wait {
+            self.regs.key_wait = true;
+            self.regs.key_wait_held = *self.keys;
+            self.regs.key_wait_latch = None;
+        }
+        // a stale key counts again once the player lets go of it
+        for i in 0..NUM_KEYS {
+            if self.regs.key_wait_held[i] && !self.keys[i] {
+                self.regs.key_wait_held[i] = false;
+            }
+        }
+        // latch the lowest newly pressed key, others pressed alongside it are ignored
+        if self.regs.key_wait_latch.is_none() {
+            self.regs.key_wait_latch = (0..NUM_KEYS)
+                .find(|&i| self.keys[i] && !self.regs.key_wait_held[i])
+                .map(|i| i as u8);
+        }
+        // finished when the latched key goes back up
+        match self.regs.key_wait_latch {
+            Some(key) if !self.keys[key as usize] => {
+                self.regs.key_wait = false;
+                self.regs.key_wait_latch = None;
+                Some(key)
+            },
+            _ => None,
         }
     }
     // gets the opcode and returns it, each opcode are 2 bytes
-    fn fetch(&mut self) -> u16 {
+    fn fetch(&mut self) -> Result<u16, Fault> {
         // remember, pc is the index of the current instruction we are executing
-        let higher_byte = self.ram[self.pc as usize] as u16;
-        let lower_byte = self.ram[(self.pc + 1) as usize] as u16;
+        let higher_byte = self.read(self.regs.pc as usize)? as u16;
+        let lower_byte = self.read(self.regs.pc as usize + 1)? as u16;
         // combines both bytes into 2 bytes as big endian
         let op = (higher_byte << 8) | lower_byte;
         // increments pointer counter two bytes for the next operation
-        self.pc += 2;
-        op
+        self.regs.pc += 2;
+        Ok(op)
     }
-    // each cpu cycle, each timer (delay and sound) will decrease once every frame
-    pub fn tick_timers(&mut self) {
-        // each timer will decrease unless they are 0
-        // in these case they will remain unchanged until changed by the game manually
-        if self.dt > 0 {
-            self.dt -= 1;
-        }
-        // when sound timer reaches 0 it will emit a beep
-        if self.st > 0 {
-            if self.st == 1 {
-                // emit a beep
-            }
-            self.st -= 1;
+    // ram access that a bad pointer can't crash
+    fn read(&self, addr: usize) -> Result<u8, Fault> {
+        self.ram.get(addr).copied().ok_or(Fault::OutOfMemory(addr))
+    }
+
+    fn write(&mut self, addr: usize, val: u8) -> Result<(), Fault> {
+        *self.ram.get_mut(addr).ok_or(Fault::OutOfMemory(addr))? = val;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // FX0A into V3, then a jump to itself once the wait is over
+    const WAIT_KEY: [u8; 4] = [0xF3, 0x0A, 0x12, 0x02];
+
+    fn waiting_emu(wait_key_release: bool) -> Emu {
+        let mut emu = Emu::new();
+        emu.set_quirks(Quirks { wait_key_release, ..Quirks::default() });
+        emu.load(&WAIT_KEY).unwrap();
+        emu
+    }
+
+    #[test]
+    fn fx0a_takes_a_held_key_straight_away() {
+        let mut emu = waiting_emu(false);
+        emu.tick();
+        assert_eq!(emu.regs.pc, 0x200);
+        emu.keypress(4, true);
+        emu.tick();
+        assert_eq!(emu.regs.pc, 0x202);
+        assert_eq!(emu.regs.v_reg[3], 4);
+    }
+
+    #[test]
+    fn fx0a_waits_for_the_key_to_go_back_up() {
+        let mut emu = waiting_emu(true);
+        emu.tick();
+        emu.keypress(5, true);
+        for _ in 0..10 {
+            emu.tick();
+            assert_eq!(emu.regs.pc, 0x200);
         }
+        emu.keypress(5, false);
+        emu.tick();
+        assert_eq!(emu.regs.pc, 0x202);
+        assert_eq!(emu.regs.v_reg[3], 5);
     }
-    // public function that gives a pointer to the display for the frontend
-    pub fn get_display(&self) -> &[bool] {
-        &self.screen
+
+    #[test]
+    fn fx0a_ignores_a_key_held_from_before() {
+        let mut emu = waiting_emu(true);
+        emu.keypress(7, true);
+        emu.tick();
+        // letting go of it doesn't answer the prompt
+        emu.keypress(7, false);
+        emu.tick();
+        assert_eq!(emu.regs.pc, 0x200);
+        assert_eq!(emu.regs.v_reg[3], 0);
+        // but pressing it again does
+        emu.keypress(7, true);
+        emu.tick();
+        emu.keypress(7, false);
+        emu.tick();
+        assert_eq!(emu.regs.pc, 0x202);
+        assert_eq!(emu.regs.v_reg[3], 7);
     }
-    // handles user key presses
-    pub fn keypress(&mut self, idx: usize, pressed: bool) {
-        self.keys[idx] = pressed;
+
+    #[test]
+    fn fx0a_takes_the_lowest_of_two_keys_pressed_together() {
+        let mut emu = waiting_emu(true);
+        emu.tick();
+        emu.keypress(9, true);
+        emu.keypress(2, true);
+        emu.tick();
+        // the other key going up first doesn't end the wait
+        emu.keypress(9, false);
+        emu.tick();
+        assert_eq!(emu.regs.pc, 0x200);
+        emu.keypress(2, false);
+        emu.tick();
+        assert_eq!(emu.regs.pc, 0x202);
+        assert_eq!(emu.regs.v_reg[3], 2);
     }
-    // copy data into ram
-    pub fn load(&mut self, data: &[u8]) {
-        let start = START_ADDR as usize;
-        let end = (START_ADDR as usize) + data.len();
-        self.ram[start..end].copy_from_slice(data);
+
+    #[test]
+    fn games_that_dont_fit_are_refused() {
+        let mut emu = waiting_emu(false);
+        assert!(emu.load(&[]).is_err());
+        assert!(emu.load(&[0; MAX_ROM_SIZE + 1]).is_err());
+        assert!(emu.reload(&[0; MAX_ROM_SIZE + 1], false).is_err());
+        // a refused reload leaves the old game running
+        assert_eq!(emu.program(), WAIT_KEY);
+        assert!(emu.load(&[0; MAX_ROM_SIZE]).is_ok());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,255 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::{rom_hash, Emu, Quirks};
+
+// how often a recording stores a checksum of the emulator's state, in frames
+// a desync is caught within a second of it happening
+pub const CHECKSUM_INTERVAL: usize = 60;
+
+// a recorded game: which game, how the emulator was set up, and the keys held on every frame
+// playing it back on a fresh emulator gives the exact same game, frame for frame
+// saved as TOML, with the keys run-length encoded since they rarely change from frame to frame
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Movie {
+    rom_hash: Hex,
+    seed: Hex,
+    quirks: Quirks,
+    ticks_per_frame: u32,
+    // keys held during each frame, bit n = key n
+    #[serde(with = "runs")]
+    frames: Vec<u16>,
+    // state hashes, as (frames played, hash)
+    checksums: Vec<(usize, Hex)>,
+}
+
+// a movie stopped matching what the emulator is doing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: usize,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "desync at frame {}: expected state {:016x}, got {:016x}", self.frame, self.expected, self.actual)
+    }
+}
+
+impl Movie {
+    // starts recording, call right after loading the game and setting quirks and seed,
+    // before the first frame runs
+    pub fn new(emu: &Emu, ticks_per_frame: u32) -> Self {
+        Self {
+            rom_hash: Hex(emu.rom_hash()),
+            seed: Hex(emu.seed()),
+            quirks: emu.quirks(),
+            ticks_per_frame,
+            frames: Vec::new(),
+            checksums: vec![(0, Hex(emu.state_hash()))],
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| format!("Invalid movie: {}", e))
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("movies are always valid TOML")
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash.0
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed.0
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn ticks_per_frame(&self) -> u32 {
+        self.ticks_per_frame
+    }
+    // length in frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+    // adds a frame, call after the frame ran with the keys that were held during it
+    pub fn record(&mut self, keys: u16, emu: &Emu) {
+        self.frames.push(keys);
+        if self.frames.len().is_multiple_of(CHECKSUM_INTERVAL) {
+            self.checksums.push((self.frames.len(), Hex(emu.state_hash())));
+        }
+    }
+    // sets an emulator up the way it was when recording started
+    // fails if the game isn't the one the movie was made with, or doesn't start out the same
+    pub fn start(&self, emu: &mut Emu, rom: &[u8]) -> Result<(), String> {
+        if rom_hash(rom) != self.rom_hash.0 {
+            return Err(format!(
+                "The movie was recorded with a different game ({:016x}, this one is {:016x})",
+                self.rom_hash.0, rom_hash(rom),
+            ));
+        }
+        emu.reset();
+        emu.set_quirks(self.quirks);
+        emu.set_seed(self.seed.0);
+        emu.load(rom)?;
+        self.check(0, emu).map_err(|e| e.to_string())
+    }
+    // keys to hold during a frame (counting from 0), None once the movie is over
+    pub fn keys(&self, frame: usize) -> Option<u16> {
+        self.frames.get(frame).copied()
+    }
+    // compares the emulator against the recording after a number of frames have played
+    // frames without a checksum always pass
+    pub fn check(&self, frames: usize, emu: &Emu) -> Result<(), Desync> {
+        let expected = match self.checksums.iter().find(|(frame, _)| *frame == frames) {
+            Some((_, hash)) => hash.0,
+            None => return Ok(()),
+        };
+        let actual = emu.state_hash();
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(Desync { frame: frames, expected, actual })
+        }
+    }
+}
+
+// a u64 written as 16 hex digits, TOML integers can't hold all of them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+struct Hex(u64);
+
+impl TryFrom<String> for Hex {
+    type Error = String;
+    fn try_from(text: String) -> Result<Self, String> {
+        match u64::from_str_radix(&text, 16) {
+            Ok(value) if text.len() == 16 => Ok(Hex(value)),
+            _ => Err(format!("{} is not a hash, expected 16 hex digits", text)),
+        }
+    }
+}
+
+impl From<Hex> for String {
+    fn from(hex: Hex) -> String {
+        format!("{:016x}", hex.0)
+    }
+}
+
+// frames are stored as [count, keys] pairs, one per run of identical frames
+mod runs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(frames: &[u16], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut runs: Vec<(usize, u16)> = Vec::new();
+        for &keys in frames {
+            match runs.last_mut() {
+                Some((count, last)) if *last == keys => *count += 1,
+                _ => runs.push((1, keys)),
+            }
+        }
+        runs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u16>, D::Error> {
+        let runs: Vec<(usize, u16)> = Vec::deserialize(deserializer)?;
+        Ok(runs.into_iter().flat_map(|(count, keys)| std::iter::repeat_n(keys, count)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BRIX: &[u8] = include_bytes!("../../c8games/BRIX");
+    const TICKS_PER_FRAME: u32 = 10;
+
+    // keys held on each frame: nothing, then the paddle left, then right
+    fn keys(frame: usize) -> u16 {
+        match frame {
+            0..=99 => 0,
+            100..=179 => 1 << 4,
+            _ => 1 << 6,
+        }
+    }
+
+    fn run_frame(emu: &mut Emu, keys: u16) {
+        emu.set_key_mask(keys);
+        for _ in 0..TICKS_PER_FRAME {
+            emu.tick();
+        }
+        emu.tick_timers();
+    }
+
+    fn record(frames: usize) -> (Movie, Emu) {
+        let mut emu = Emu::new();
+        emu.set_seed(7);
+        emu.load(BRIX).unwrap();
+        let mut movie = Movie::new(&emu, TICKS_PER_FRAME);
+        for frame in 0..frames {
+            run_frame(&mut emu, keys(frame));
+            movie.record(keys(frame), &emu);
+        }
+        (movie, emu)
+    }
+
+    #[test]
+    fn playback_matches_the_recording() {
+        let (movie, recorded) = record(300);
+        let movie = Movie::from_toml(&movie.to_toml()).unwrap();
+        assert_eq!(movie.len(), 300);
+        // a different seed to start with, the movie puts its own back
+        let mut emu = Emu::new();
+        emu.set_seed(99);
+        movie.start(&mut emu, BRIX).unwrap();
+        for frame in 0..movie.len() {
+            run_frame(&mut emu, movie.keys(frame).unwrap());
+            movie.check(frame + 1, &emu).unwrap();
+        }
+        assert_eq!(movie.keys(300), None);
+        assert_eq!(emu.state_hash(), recorded.state_hash());
+    }
+
+    #[test]
+    fn different_keys_are_caught_at_the_next_checksum() {
+        let (movie, _) = record(300);
+        let mut emu = Emu::new();
+        movie.start(&mut emu, BRIX).unwrap();
+        let mut desync = None;
+        for frame in 0..movie.len() {
+            // the paddle goes right instead of left
+            let keys = if frame == 120 { 1 << 6 } else { movie.keys(frame).unwrap() };
+            run_frame(&mut emu, keys);
+            if let Err(e) = movie.check(frame + 1, &emu) {
+                desync = Some(e);
+                break;
+            }
+        }
+        assert_eq!(desync.map(|e| e.frame), Some(3 * CHECKSUM_INTERVAL));
+    }
+
+    #[test]
+    fn keys_are_saved_as_runs() {
+        let (movie, _) = record(300);
+        let toml: toml::Table = toml::from_str(&movie.to_toml()).unwrap();
+        let runs = toml["frames"].as_array().unwrap();
+        assert_eq!(runs.len(), 3);
+        assert_eq!(runs[1].as_array().unwrap(), &[toml::Value::Integer(80), toml::Value::Integer(16)]);
+    }
+
+    #[test]
+    fn another_game_is_refused() {
+        let (movie, _) = record(10);
+        let mut emu = Emu::new();
+        let err = movie.start(&mut emu, include_bytes!("../../c8games/PONG")).unwrap_err();
+        assert!(err.contains("different game"), "{}", err);
+    }
+}
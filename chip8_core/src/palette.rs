@@ -0,0 +1,99 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+// a 24-bit color, written as "#rrggbb" in config files
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    const fn hex(value: u32) -> Self {
+        Rgb((value >> 16) as u8, (value >> 8) as u8, value as u8)
+    }
+}
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+    fn try_from(text: String) -> Result<Self, String> {
+        let digits = text.strip_prefix('#').unwrap_or(&text);
+        match u32::from_str_radix(digits, 16) {
+            Ok(value) if digits.len() == 6 => Ok(Rgb::hex(value)),
+            _ => Err(format!("{} is not a color, expected #rrggbb", text)),
+        }
+    }
+}
+
+impl From<Rgb> for String {
+    fn from(color: Rgb) -> String {
+        color.to_string()
+    }
+}
+
+impl fmt::Display for Rgb {
+    // "#rrggbb", which is also what the browser's canvas understands
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+// the colors a frontend draws the display with
+// chip-8 only needs the first two, the other two are there for XO-CHIP's second bitplane:
+// [0] pixel off, [1] pixel on in plane 1, [2] on in plane 2, [3] on in both
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+// the palettes every frontend offers, by name
+const BUILT_IN: [(&str, [u32; 4]); 6] = [
+    ("classic", [0x000000, 0xffffff, 0xaaaaaa, 0x555555]),
+    // amber and green monochrome monitors
+    ("amber", [0x1a0f00, 0xffb000, 0x9c6b00, 0xffe0a0]),
+    ("green", [0x001a00, 0x33ff33, 0x1a991a, 0xb3ffb3]),
+    // the original Game Boy's greenish LCD
+    ("lcd", [0x9bbc0f, 0x0f380f, 0x306230, 0x8bac0f]),
+    ("high-contrast", [0x000000, 0xffff00, 0x00ffff, 0xffffff]),
+    // from the Okabe-Ito set, which stays distinguishable with the common kinds of color blindness
+    ("colorblind", [0x000000, 0xe69f00, 0x56b4e9, 0xf0e442]),
+];
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::named("classic").unwrap()
+    }
+}
+
+impl Palette {
+    // names of the built-in palettes, in the order a frontend should list them
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        BUILT_IN.iter().map(|(name, _)| *name)
+    }
+
+    pub fn named(name: &str) -> Option<Self> {
+        BUILT_IN.iter().find(|(n, _)| *n == name).map(|(_, colors)| Self {
+            colors: colors.map(Rgb::hex),
+        })
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Rgb {
+        self.colors[1]
+    }
+    // color of a pixel given its value, for chip-8 displays that's just on or off
+    pub fn color(&self, lit: bool) -> Rgb {
+        self.colors[lit as usize]
+    }
+    // somewhere between background (0) and foreground (255), for pixels that are fading out
+    pub fn shade(&self, intensity: u8) -> Rgb {
+        let mix = |bg: u8, fg: u8| {
+            let (bg, fg, t) = (bg as u32, fg as u32, intensity as u32);
+            ((bg * (255 - t) + fg * t) / 255) as u8
+        };
+        let (bg, fg) = (self.background(), self.foreground());
+        Rgb(mix(bg.0, fg.0), mix(bg.1, fg.1), mix(bg.2, fg.2))
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// games erase sprites by drawing them again (XOR), so anything that moves is missing from
+// some frames and flickers, these modes smooth that over the way an old CRT did
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisplayMode {
+    // exactly what is in the screen buffer
+    #[default]
+    Raw,
+    // lit pixels fade out over a few frames instead of switching off (phosphor ghosting)
+    Decay,
+    // average of this frame and the last one
+    Blend,
+    // a pixel is shown if it was lit in either of the last two frames
+    EitherFrame,
+}
+
+impl DisplayMode {
+    pub const ALL: [DisplayMode; 4] = [DisplayMode::Raw, DisplayMode::Decay, DisplayMode::Blend, DisplayMode::EitherFrame];
+    // the same names used in config files
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisplayMode::Raw => "raw",
+            DisplayMode::Decay => "decay",
+            DisplayMode::Blend => "blend",
+            DisplayMode::EitherFrame => "either-frame",
+        }
+    }
+
+    pub fn named(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+}
+
+// default share of brightness a pixel loses each frame in Decay mode
+pub const DEFAULT_DECAY: f32 = 0.4;
+
+// turns the on/off screen buffer into per-pixel brightness (0 to 255)
+// call update() once per 60Hz frame, after the frame's ticks
+pub struct Persistence {
+    mode: DisplayMode,
+    decay: f32,
+    intensity: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    // the screen as it was on the previous update
+    last: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+}
+
+impl Default for Persistence {
+    fn default() -> Self {
+        Self::new(DisplayMode::Raw)
+    }
+}
+
+impl Persistence {
+    pub fn new(mode: DisplayMode) -> Self {
+        Self {
+            mode,
+            decay: DEFAULT_DECAY,
+            intensity: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            last: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn mode(&self) -> DisplayMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: DisplayMode) {
+        self.mode = mode;
+    }
+    // share of its brightness an unlit pixel loses each frame in Decay mode,
+    // 1.0 switches off at once, small values leave long trails
+    pub fn set_decay(&mut self, decay: f32) {
+        self.decay = decay.clamp(0.01, 1.0);
+    }
+    // works out this frame's brightness from the emulator's display, see Emu::get_display
+    pub fn update(&mut self, screen: &[bool]) -> &[u8] {
+        let keep = 1.0 - self.decay;
+        for (i, &lit) in screen.iter().enumerate() {
+            let last = self.last[i];
+            self.intensity[i] = match self.mode {
+                DisplayMode::Raw => lit as u8 * 255,
+                DisplayMode::Decay if lit => 255,
+                DisplayMode::Decay => (self.intensity[i] as f32 * keep) as u8,
+                DisplayMode::Blend => (lit as u8 + last as u8) * 127 + (lit && last) as u8,
+                DisplayMode::EitherFrame => (lit || last) as u8 * 255,
+            };
+            self.last[i] = lit;
+        }
+        &self.intensity
+    }
+    // brightness from the last update
+    pub fn intensity(&self) -> &[u8] {
+        &self.intensity
+    }
+}
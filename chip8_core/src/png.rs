@@ -0,0 +1,160 @@
+use crate::{Palette, Renderer};
+
+// a minimal PNG encoder, enough for screenshots without pulling in an image library
+// (which would bloat the wasm build for one feature)
+// pixels are stored with zlib's "stored" blocks, i.e. uncompressed, so files are larger
+// than they need to be but still small at chip-8 sizes
+pub fn encode_png(rgba: &[u8], width: usize, height: usize) -> Vec<u8> {
+    assert_eq!(rgba.len(), width * height * 4, "image size doesn't match its dimensions");
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, truecolor with alpha, default compression/filter, no interlacing
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+
+    // every row starts with its filter type, 0 = none
+    let mut raw = Vec::with_capacity((width * 4 + 1) * height);
+    for row in rgba.chunks_exact(width * 4) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// the current display as a PNG, each chip-8 pixel scale x scale pixels big
+pub fn screenshot(screen: &[bool], scale: usize, palette: &Palette) -> Vec<u8> {
+    let mut renderer = Renderer::new(scale);
+    renderer.set_palette(*palette);
+    let frame = renderer.render(screen).to_vec();
+    encode_png(&frame, renderer.width(), renderer.height())
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    // the checksum covers the chunk type and its data
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// zlib stream made of uncompressed deflate blocks (at most 65535 bytes each)
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate, 32K window, no dictionary, fastest
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        // an empty stream still needs one (final) block
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u8;
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// CRC-32 as PNG uses it (polynomial 0xEDB88320), bit by bit since images are small
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // splits a PNG into its chunks, checking every CRC on the way
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let body = &rest[4..8 + len];
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc32(body), crc);
+            chunks.push((body[..4].try_into().unwrap(), body[4..].to_vec()));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    // undoes zlib_stored, checking the block headers and the checksum
+    fn unstore(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(&zlib[..2], [0x78, 0x01]);
+        let mut data = Vec::new();
+        let mut pos = 2;
+        loop {
+            let last = zlib[pos];
+            let len = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]);
+            assert_eq!(u16::from_le_bytes([zlib[pos + 3], zlib[pos + 4]]), !len);
+            data.extend_from_slice(&zlib[pos + 5..pos + 5 + len as usize]);
+            pos += 5 + len as usize;
+            if last == 1 {
+                break;
+            }
+        }
+        assert_eq!(zlib[pos..], adler32(&data).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn png_holds_the_pixels_row_by_row() {
+        let rgba: Vec<u8> = (0..2 * 3 * 4).map(|i| i as u8).collect();
+        let png = encode_png(&rgba, 2, 3);
+        let chunks = chunks(&png);
+        let kinds: Vec<_> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 3, 8, 6, 0, 0, 0]);
+        let raw = unstore(&chunks[1].1);
+        let rows: Vec<_> = raw.chunks(2 * 4 + 1).collect();
+        assert_eq!(rows.len(), 3);
+        for (y, row) in rows.iter().enumerate() {
+            assert_eq!(row[0], 0);
+            assert_eq!(row[1..], rgba[y * 8..(y + 1) * 8]);
+        }
+    }
+
+    #[test]
+    fn big_images_span_several_blocks() {
+        // a full-size screenshot at the default scale is well over one 64K block
+        let png = screenshot(&[true; 64 * 32], 15, &Palette::default());
+        let chunks = chunks(&png);
+        let raw = unstore(&chunks[1].1);
+        assert_eq!(raw.len(), (64 * 15 * 4 + 1) * 32 * 15);
+    }
+}
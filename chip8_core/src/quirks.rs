@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+// the family of interpreters a game was written for
+// this emulator only runs plain chip-8 instructions, but knowing the platform
+// tells us which quirks the game expects
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Platform {
+    // the original interpreter on the COSMAC VIP
+    #[default]
+    #[serde(rename = "chip-8")]
+    Chip8,
+    // CHIP-48 / SUPER-CHIP on the HP 48 calculators
+    SuperChip,
+    // Octo's XO-CHIP extensions
+    XoChip,
+}
+
+// behaviours that differ between chip-8 interpreters
+// every quirk is off by default, which keeps the emulator working like it always has
+// (roughly SUPER-CHIP behaviour, minus the BXNN jump)
+// in a config file a quirks table replaces the whole set, missing entries are off
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quirks {
+    // FX0A waits for a key to be pressed and then released (like the original COSMAC VIP)
+    // instead of taking whichever key is held the moment the instruction runs
+    pub wait_key_release: bool,
+    // 8XY6/8XYE copy VY into VX before shifting, instead of shifting VX in place
+    pub shift_vy: bool,
+    // FX55/FX65 leave I pointing just past the last register stored/loaded
+    pub load_store_increment_i: bool,
+    // BNNN jumps to VX + NNN (X being the first digit of NNN) instead of V0 + NNN
+    pub jump_vx: bool,
+    // 8XY1/8XY2/8XY3 clear VF afterwards
+    pub vf_reset: bool,
+}
+
+impl Platform {
+    // the same names used in config files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip-8",
+            Platform::SuperChip => "super-chip",
+            Platform::XoChip => "xo-chip",
+        }
+    }
+}
+
+impl Quirks {
+    // the quirks a game written for a given platform most likely relies on
+    pub fn for_platform(platform: Platform) -> Self {
+        match platform {
+            Platform::Chip8 => Self {
+                wait_key_release: true,
+                shift_vy: true,
+                load_store_increment_i: true,
+                jump_vx: false,
+                vf_reset: true,
+            },
+            Platform::SuperChip => Self {
+                jump_vx: true,
+                ..Self::default()
+            },
+            Platform::XoChip => Self {
+                shift_vy: true,
+                load_store_increment_i: true,
+                ..Self::default()
+            },
+        }
+    }
+    // one bit per quirk, in the order they are declared, for packing into save states and packets
+    pub fn bits(&self) -> u8 {
+        self.flags().iter().enumerate().fold(0, |bits, (i, &on)| bits | (on as u8) << i)
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        let on = |i: u8| bits & (1 << i) != 0;
+        Self {
+            wait_key_release: on(0),
+            shift_vy: on(1),
+            load_store_increment_i: on(2),
+            jump_vx: on(3),
+            vf_reset: on(4),
+        }
+    }
+
+    fn flags(&self) -> [bool; 5] {
+        [self.wait_key_release, self.shift_vy, self.load_store_increment_i, self.jump_vx, self.vf_reset]
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{DisplayMode, Filter, Palette, Persistence, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::filter::Pixel;
+
+// bytes per pixel in the output, red, green, blue and alpha
+const RGBA: usize = 4;
+
+// turns the display into an RGBA8 image both frontends can upload in one go
+// (an SDL texture on desktop, an ImageData on the web)
+// instead of drawing a rectangle per lit pixel
+pub struct Renderer {
+    scale: usize,
+    palette: Palette,
+    // optional anti-flicker pass, raw by default
+    persistence: Persistence,
+    // upscaling filter, plain nearest-neighbor by default
+    filter: Filter,
+    buffer: Vec<u8>,
+}
+
+impl Renderer {
+    pub fn new(scale: usize) -> Self {
+        let scale = scale.max(1);
+        Self {
+            scale,
+            palette: Palette::default(),
+            persistence: Persistence::default(),
+            filter: Filter::default(),
+            buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * scale * scale * RGBA],
+        }
+    }
+    // size of the image render() produces, in pixels
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH * self.scale
+    }
+
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT * self.scale
+    }
+    // bytes per row of the image, what SDL calls the pitch
+    pub fn pitch(&self) -> usize {
+        self.width() * RGBA
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: usize) {
+        let scale = scale.max(1);
+        if scale != self.scale {
+            self.scale = scale;
+            self.buffer = vec![0; self.pitch() * self.height()];
+        }
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn display_mode(&self) -> DisplayMode {
+        self.persistence.mode()
+    }
+
+    pub fn set_display_mode(&mut self, mode: DisplayMode) {
+        self.persistence.set_mode(mode);
+    }
+
+    pub fn set_decay(&mut self, decay: f32) {
+        self.persistence.set_decay(decay);
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+    // filters work at any scale, but look best when it is a multiple of
+    // their own factor (2 for Scale2x and Hq2x, 3 for Scale3x)
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+    // draws one frame from the emulator's display (see Emu::get_display)
+    // call once per 60Hz frame so the anti-flicker pass keeps time
+    pub fn render(&mut self, screen: &[bool]) -> &[u8] {
+        // every brightness level maps to a color, so work those out once
+        // rather than once per pixel
+        let mut colors = [[0u8; RGBA]; 256];
+        for (level, color) in colors.iter_mut().enumerate() {
+            let rgb = self.palette.shade(level as u8);
+            *color = [rgb.0, rgb.1, rgb.2, 0xFF];
+        }
+        let intensity = self.persistence.update(screen);
+        let base: Vec<Pixel> = intensity.iter().map(|&level| colors[level as usize]).collect();
+        // the filter does the first (smart) part of the scaling...
+        let (image, src_w) = self.filter.upscale(&base, SCREEN_WIDTH, SCREEN_HEIGHT);
+        let src_h = image.len() / src_w;
+        // ...and nearest-neighbor takes it the rest of the way
+        let (width, height, pitch) = (self.width(), self.height(), self.pitch());
+        let mut last_src_y = usize::MAX;
+        for y in 0..height {
+            let src_y = y * src_h / height;
+            let line = y * pitch;
+            if src_y == last_src_y {
+                // same source row as the line above, just copy it down
+                self.buffer.copy_within(line - pitch..line, line);
+                continue;
+            }
+            last_src_y = src_y;
+            let src_row = &image[src_y * src_w..(src_y + 1) * src_w];
+            for (x, px) in self.buffer[line..line + pitch].chunks_exact_mut(RGBA).enumerate() {
+                px.copy_from_slice(&src_row[x * src_w / width]);
+            }
+        }
+        self.filter.post_process(&mut self.buffer, width, self.scale);
+        &self.buffer
+    }
+    // the image from the last render()
+    pub fn frame(&self) -> &[u8] {
+        &self.buffer
+    }
+    // the image from the last render() as a PNG, exactly what the player is looking at
+    pub fn frame_png(&self) -> Vec<u8> {
+        crate::encode_png(&self.buffer, self.width(), self.height())
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::{Analysis, EnvSpec, Keymap, Platform, Quirks};
+
+// settings for the games in c8games, shipped inside the library
+const BUNDLED: &str = include_str!("roms.toml");
+
+// 64-bit FNV-1a hash of a ROM's bytes, used to recognise a game no matter what its file is called
+// not meant to be cryptographically strong, just cheap and without dependencies
+pub fn rom_hash(data: &[u8]) -> u64 {
+    let mut hash = Fnv::default();
+    hash.write(data);
+    hash.finish()
+}
+
+// the same hash fed a piece at a time, for hashing things that aren't one slice
+pub(crate) struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    pub(crate) fn write(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// everything we know about one game
+// every field is optional, so a user file only needs to list what it changes
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RomProfile {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    // how many instructions to run per 60Hz frame
+    pub ticks_per_frame: Option<u32>,
+    // keyboard bindings layered on top of the default keymap
+    pub keymap: Option<Keymap>,
+    // controller bindings per player, layered on top of Keymap::gamepad()
+    pub gamepad: Option<Vec<Keymap>>,
+    // short explanation of the controls to show the player
+    pub key_help: Option<String>,
+    // rewards and game over for reinforcement learning, see Environment
+    pub env: Option<EnvSpec>,
+}
+
+impl RomProfile {
+    // copies over every field the other profile sets
+    pub fn merge(&mut self, other: &RomProfile) {
+        fn take<T: Clone>(field: &mut Option<T>, other: &Option<T>) {
+            if other.is_some() {
+                field.clone_from(other);
+            }
+        }
+        take(&mut self.title, &other.title);
+        take(&mut self.author, &other.author);
+        take(&mut self.platform, &other.platform);
+        take(&mut self.quirks, &other.quirks);
+        take(&mut self.ticks_per_frame, &other.ticks_per_frame);
+        take(&mut self.keymap, &other.keymap);
+        take(&mut self.gamepad, &other.gamepad);
+        take(&mut self.key_help, &other.key_help);
+        take(&mut self.env, &other.env);
+    }
+    // fills in the platform and quirks from a scan of the game when the profile doesn't
+    // say and the scan is sure enough of itself
+    pub fn fill_from(&mut self, analysis: &Analysis) {
+        if self.platform.is_none() && self.quirks.is_none() && analysis.is_confident() {
+            self.platform = Some(analysis.platform);
+            self.quirks = Some(analysis.quirks);
+        }
+    }
+    // quirks to run this game with, falling back to the platform's usual ones
+    pub fn quirks(&self) -> Option<Quirks> {
+        self.quirks.or_else(|| self.platform.map(Quirks::for_platform))
+    }
+    // the default keymap with this game's bindings on top
+    pub fn keyboard(&self) -> Keymap {
+        let mut keymap = Keymap::default();
+        if let Some(overrides) = &self.keymap {
+            keymap.merge(overrides);
+        }
+        keymap
+    }
+    // controller keymap for a player (0 based), with this game's bindings on top
+    pub fn gamepad(&self, player: usize) -> Keymap {
+        let mut keymap = Keymap::gamepad();
+        if let Some(overrides) = self.gamepad.as_ref().and_then(|pads| pads.get(player)) {
+            keymap.merge(overrides);
+        }
+        keymap
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let pads = self.gamepad.iter().flatten();
+        for keymap in self.keymap.iter().chain(pads) {
+            keymap.validate()?;
+        }
+        if let Some(env) = &self.env {
+            env.validate()?;
+        }
+        Ok(())
+    }
+}
+
+// game profiles keyed by rom_hash, written as 16 hex digits
+// [9a3c1f7e0b2d4c68]
+// title = "Pong"
+// ticks_per_frame = 10
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RomDb {
+    profiles: BTreeMap<String, RomProfile>,
+}
+
+impl RomDb {
+    // the profiles shipped with the emulator
+    pub fn bundled() -> Self {
+        toml::from_str(BUNDLED).expect("bundled roms.toml is invalid")
+    }
+
+    pub fn lookup(&self, hash: u64) -> Option<&RomProfile> {
+        self.profiles.get(&format!("{:016x}", hash))
+    }
+    // user overrides are merged field by field, so a user entry can change one setting
+    // of a bundled game without repeating the rest
+    pub fn merge(&mut self, overrides: &RomDb) {
+        for (hash, profile) in &overrides.profiles {
+            self.profiles.entry(hash.to_lowercase()).or_default().merge(profile);
+        }
+    }
+
+    pub fn insert(&mut self, hash: u64, profile: RomProfile) {
+        self.profiles.insert(format!("{:016x}", hash), profile);
+    }
+    // checks a database loaded from a user file, returns the first problem
+    pub fn validate(&self) -> Result<(), String> {
+        for (hash, profile) in &self.profiles {
+            if hash.len() != 16 || u64::from_str_radix(hash, 16).is_err() {
+                return Err(format!("{} is not a ROM hash (16 hex digits)", hash));
+            }
+            profile.validate().map_err(|e| format!("{}: {}", hash, e))?;
+        }
+        Ok(())
+    }
+}
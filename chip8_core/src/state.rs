@@ -0,0 +1,210 @@
+use crate::{Emu, Quirks, Regs, SaveState, MAX_ROM_SIZE, NUM_KEYS, RAM_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH, STACK_SIZE};
+
+// "C8S" and the format version, bumped whenever the layout below changes
+const MAGIC: &[u8; 3] = b"C8S";
+const VERSION: u8 = 1;
+
+// size of every save state in bytes, the same for every game, so a host can set aside room
+// for one up front (libretro asks for exactly this)
+pub const SAVE_STATE_SIZE: usize = 4 // magic and version
+    + 2 + 16 + 2 + 2 + STACK_SIZE * 2 + 2 // pc, V0-VF, I, sp, stack, dt and st
+    + 1 + 2 + 1 + 2 + 8 // FX0A wait, held keys, latched key, tested keys, rng
+    + RAM_SIZE
+    + SCREEN_WIDTH * SCREEN_HEIGHT / 8 // the display, 8 pixels per byte
+    + 2 + 1 + 8 + 2 + 8; // keys, quirks, rom hash, rom size and seed
+
+// save states as bytes, for writing to disk or handing to a host application
+// everything is little-endian and fixed size, see SAVE_STATE_SIZE
+impl SaveState {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let emu = &self.0;
+        let regs = &emu.regs;
+        let mut data = Vec::with_capacity(SAVE_STATE_SIZE);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&regs.pc.to_le_bytes());
+        data.extend_from_slice(&regs.v_reg);
+        data.extend_from_slice(&regs.i_reg.to_le_bytes());
+        data.extend_from_slice(&regs.sp.to_le_bytes());
+        for val in regs.stack {
+            data.extend_from_slice(&val.to_le_bytes());
+        }
+        data.extend_from_slice(&[regs.dt, regs.st, regs.key_wait as u8]);
+        data.extend_from_slice(&mask(&regs.key_wait_held).to_le_bytes());
+        data.push(regs.key_wait_latch.unwrap_or(0xFF));
+        data.extend_from_slice(&regs.keys_tested.to_le_bytes());
+        data.extend_from_slice(&regs.rng.to_le_bytes());
+        data.extend_from_slice(&emu.ram);
+        for pixels in emu.screen.chunks(8) {
+            data.push(pixels.iter().fold(0, |byte, &on| byte << 1 | on as u8));
+        }
+        data.extend_from_slice(&emu.key_mask().to_le_bytes());
+        data.push(emu.quirks.bits());
+        data.extend_from_slice(&emu.rom_hash.to_le_bytes());
+        data.extend_from_slice(&(emu.rom_len as u16).to_le_bytes());
+        data.extend_from_slice(&emu.seed.to_le_bytes());
+        data
+    }
+    // checks everything it can, so a blob from somewhere else can't put the emulator in an impossible state
+    pub fn from_bytes(data: &[u8]) -> Result<SaveState, String> {
+        if data.len() != SAVE_STATE_SIZE || &data[..3] != MAGIC {
+            return Err("Not a save state".to_string());
+        }
+        if data[3] != VERSION {
+            return Err(format!("Save state version {} isn't supported", data[3]));
+        }
+        let mut data = Reader(&data[4..]);
+        let mut regs = Regs::new(0);
+        regs.pc = data.u16();
+        regs.v_reg.copy_from_slice(data.take(16));
+        regs.i_reg = data.u16();
+        regs.sp = data.u16();
+        for val in regs.stack.iter_mut() {
+            *val = data.u16();
+        }
+        regs.dt = data.u8();
+        regs.st = data.u8();
+        regs.key_wait = data.u8() != 0;
+        regs.key_wait_held = unmask(data.u16());
+        regs.key_wait_latch = Some(data.u8()).filter(|&key| key != 0xFF);
+        regs.keys_tested = data.u16();
+        regs.rng = data.u64();
+        let mut emu = Emu::new();
+        emu.regs = regs;
+        emu.ram.copy_from_slice(data.take(RAM_SIZE));
+        let packed = data.take(SCREEN_WIDTH * SCREEN_HEIGHT / 8);
+        for (i, px) in emu.screen.iter_mut().enumerate() {
+            *px = packed[i / 8] & (0x80 >> (i % 8)) != 0;
+        }
+        emu.keys = unmask(data.u16());
+        emu.quirks = Quirks::from_bits(data.u8());
+        emu.rom_hash = data.u64();
+        emu.rom_len = data.u16() as usize;
+        emu.seed = data.u64();
+        if emu.regs.pc as usize >= RAM_SIZE || emu.regs.i_reg as usize >= RAM_SIZE {
+            return Err("Save state registers point outside memory".to_string());
+        }
+        if emu.regs.sp as usize > STACK_SIZE {
+            return Err("Save state stack is too deep".to_string());
+        }
+        if emu.regs.key_wait_latch.is_some_and(|key| key as usize >= NUM_KEYS) {
+            return Err("Save state waits on a key that doesn't exist".to_string());
+        }
+        if emu.rom_len > MAX_ROM_SIZE {
+            return Err("Save state game doesn't fit in memory".to_string());
+        }
+        Ok(SaveState(Box::new(emu)))
+    }
+}
+
+fn mask(keys: &[bool; NUM_KEYS]) -> u16 {
+    keys.iter().enumerate().fold(0, |mask, (i, &held)| mask | (held as u16) << i)
+}
+
+fn unmask(mask: u16) -> [bool; NUM_KEYS] {
+    std::array::from_fn(|i| mask & (1 << i) != 0)
+}
+// reads the fields back in order, the length was checked up front
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> &'a [u8] {
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        head
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take(2).try_into().unwrap())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take(8).try_into().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BRIX: &[u8] = include_bytes!("../../c8games/BRIX");
+    // where some fields sit in the blob
+    const PC_AT: usize = 4;
+    const SP_AT: usize = 24;
+    const LATCH_AT: usize = 63;
+
+    fn run_frames(emu: &mut Emu, frames: usize) {
+        for _ in 0..frames {
+            for _ in 0..10 {
+                emu.tick();
+            }
+            emu.tick_timers();
+        }
+    }
+
+    fn brix() -> Emu {
+        let mut emu = Emu::new();
+        emu.set_quirks(Quirks { wait_key_release: true, ..Quirks::default() });
+        emu.set_seed(11);
+        emu.load(BRIX).unwrap();
+        emu.keypress(4, true);
+        run_frames(&mut emu, 90);
+        emu
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut emu = brix();
+        let bytes = emu.save_state().to_bytes();
+        assert_eq!(bytes.len(), SAVE_STATE_SIZE);
+        let mut copy = Emu::new();
+        copy.load_state(&SaveState::from_bytes(&bytes).unwrap());
+        assert_eq!(copy.state_hash(), emu.state_hash());
+        assert_eq!(copy.get_display(), emu.get_display());
+        assert_eq!(copy.save_state().to_bytes(), bytes);
+        // and they carry on the same, random numbers included
+        run_frames(&mut emu, 120);
+        run_frames(&mut copy, 120);
+        assert_eq!(copy.state_hash(), emu.state_hash());
+    }
+
+    #[test]
+    fn a_key_wait_carries_over() {
+        // FX0A into V3, then jump to itself
+        let mut emu = Emu::new();
+        emu.set_quirks(Quirks { wait_key_release: true, ..Quirks::default() });
+        emu.load(&[0xF3, 0x0A, 0x12, 0x02]).unwrap();
+        emu.tick();
+        emu.keypress(6, true);
+        emu.tick();
+        let bytes = emu.save_state().to_bytes();
+        let mut copy = Emu::new();
+        copy.load_state(&SaveState::from_bytes(&bytes).unwrap());
+        copy.keypress(6, false);
+        copy.tick();
+        assert_eq!(copy.regs.pc, 0x202);
+        assert_eq!(copy.regs.v_reg[3], 6);
+    }
+
+    #[test]
+    fn broken_states_are_refused() {
+        let bytes = brix().save_state().to_bytes();
+        let broken = |at: usize, value: &[u8]| {
+            let mut bytes = bytes.clone();
+            bytes[at..at + value.len()].copy_from_slice(value);
+            SaveState::from_bytes(&bytes).err().unwrap()
+        };
+        assert!(SaveState::from_bytes(&bytes[..SAVE_STATE_SIZE - 1]).is_err());
+        assert_eq!(broken(0, b"X"), "Not a save state");
+        assert!(broken(3, &[VERSION + 1]).contains("version"));
+        assert!(broken(PC_AT, &0x1000u16.to_le_bytes()).contains("outside memory"));
+        assert!(broken(SP_AT, &[STACK_SIZE as u8 + 1, 0]).contains("too deep"));
+        assert!(broken(LATCH_AT, &[NUM_KEYS as u8]).contains("key"));
+        let rom_len_at = SAVE_STATE_SIZE - 10;
+        assert!(broken(rom_len_at, &(MAX_ROM_SIZE as u16 + 1).to_le_bytes()).contains("fit"));
+    }
+}
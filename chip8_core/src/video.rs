@@ -0,0 +1,93 @@
+use std::io::{self, Write};
+use crate::{Palette, Renderer};
+
+// uncompressed formats a FrameStream can write
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamFormat {
+    // YUV4MPEG2 with full resolution chroma (4:4:4), what ffmpeg, mpv and x264 read from a pipe
+    Y4m,
+    // bare RGBA8 frames one after another, no header, the exact colors (Y4M goes through YUV)
+    // e.g. ffmpeg -f rawvideo -pixel_format rgba -video_size 640x320 -framerate 60 -i -
+    Raw,
+}
+
+impl StreamFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            StreamFormat::Y4m => "y4m",
+            StreamFormat::Raw => "raw",
+        }
+    }
+
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "y4m" => Some(StreamFormat::Y4m),
+            "raw" => Some(StreamFormat::Raw),
+            _ => None,
+        }
+    }
+}
+
+// writes every emulated frame to a file or pipe as it happens, nothing is kept in memory
+// frames are drawn with their own renderer, so the recording gets the palette but not
+// whatever filter the player has on
+pub struct FrameStream<W: Write> {
+    out: W,
+    format: StreamFormat,
+    renderer: Renderer,
+    frames: usize,
+}
+
+impl<W: Write> FrameStream<W> {
+    pub fn new(out: W, format: StreamFormat, scale: usize, palette: Palette) -> Self {
+        let mut renderer = Renderer::new(scale);
+        renderer.set_palette(palette);
+        Self { out, format, renderer, frames: 0 }
+    }
+    // call once per 60Hz frame with the emulator's display (see Emu::get_display)
+    pub fn write_frame(&mut self, screen: &[bool]) -> io::Result<()> {
+        let (width, height) = (self.renderer.width(), self.renderer.height());
+        let rgba = self.renderer.render(screen);
+        match self.format {
+            StreamFormat::Raw => self.out.write_all(rgba)?,
+            StreamFormat::Y4m => {
+                if self.frames == 0 {
+                    // 60fps, progressive, square pixels
+                    writeln!(self.out, "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444", width, height)?;
+                }
+                // planar, all of Y then all of U then all of V
+                let mut planes = vec![0u8; width * height * 3];
+                let (y_plane, rest) = planes.split_at_mut(width * height);
+                let (u_plane, v_plane) = rest.split_at_mut(width * height);
+                for (i, px) in rgba.chunks_exact(4).enumerate() {
+                    let (y, u, v) = yuv(px[0], px[1], px[2]);
+                    y_plane[i] = y;
+                    u_plane[i] = u;
+                    v_plane[i] = v;
+                }
+                self.out.write_all(b"FRAME\n")?;
+                self.out.write_all(&planes)?;
+            },
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames
+    }
+    // flushes and hands back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// BT.601 studio range, what players assume when a Y4M doesn't say otherwise
+fn yuv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    (y as u8, u as u8, v as u8)
+}
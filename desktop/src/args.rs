@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+use crate::netplay::DEFAULT_INPUT_DELAY;
+
+pub const USAGE: &str = "\
+Usage: cargo run path/to/game [options]
+Options:
+    --config FILE    settings file (default: chip8.toml if it exists)
+    --scale N        initial window size as a multiple of 64x32 (default: 15)
+    --fullscreen     start in fullscreen
+    --fit            stretch to fill the window instead of whole-number scaling
+    --record FILE    record the keys pressed into an input movie, saved on exit
+    --play FILE      play back an input movie, then hand over control
+    --peer ADDR      play against another emulator over UDP, e.g. 192.168.1.20:7000
+    --bind ADDR      address to listen on for netplay (default: 0.0.0.0:7000)
+    --player N       which player this side is in netplay, 1 or 2 (default: 1)
+    --input-delay N  netplay input delay in frames (default: 2)
+    --cheats         type cheat commands into the terminal, see help there
+    --no-watch       don't reload the game when its file changes
+    --keep-state     keep the registers and memory outside the program when reloading";
+
+// default window size as a multiple of the chip-8 display
+pub const DEFAULT_SCALE: u32 = 15;
+// where netplay listens unless told otherwise
+pub const DEFAULT_BIND: &str = "0.0.0.0:7000";
+
+// command line options
+pub struct Args {
+    pub rom: String,
+    pub config: Option<PathBuf>,
+    pub scale: u32,
+    pub fullscreen: bool,
+    pub fit: bool,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub peer: Option<String>,
+    pub bind: String,
+    // 0 based
+    pub player: usize,
+    pub input_delay: usize,
+    pub cheats: bool,
+    pub watch: bool,
+    pub keep_state: bool,
+}
+
+// reads the options that follow the program name
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut rom = None;
+    let mut parsed = Args {
+        rom: String::new(),
+        config: None,
+        scale: DEFAULT_SCALE,
+        fullscreen: false,
+        fit: false,
+        record: None,
+        play: None,
+        peer: None,
+        bind: DEFAULT_BIND.to_string(),
+        player: 0,
+        input_delay: DEFAULT_INPUT_DELAY,
+        cheats: false,
+        watch: true,
+        keep_state: false,
+    };
+    while let Some(arg) = args.next() {
+        // options that take a value
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--config" => parsed.config = Some(PathBuf::from(value()?)),
+            "--scale" => {
+                let text = value()?;
+                parsed.scale = match text.parse() {
+                    Ok(scale) if scale > 0 => scale,
+                    _ => return Err(format!("Invalid scale: {}", text)),
+                };
+            },
+            "--fullscreen" => parsed.fullscreen = true,
+            "--fit" => parsed.fit = true,
+            "--record" => parsed.record = Some(value()?),
+            "--play" => parsed.play = Some(value()?),
+            "--peer" => parsed.peer = Some(value()?),
+            "--bind" => parsed.bind = value()?,
+            "--player" => {
+                let text = value()?;
+                parsed.player = match text.as_str() {
+                    "1" => 0,
+                    "2" => 1,
+                    _ => return Err(format!("Invalid player: {}, expected 1 or 2", text)),
+                };
+            },
+            "--input-delay" => {
+                let text = value()?;
+                parsed.input_delay = text.parse().map_err(|_| format!("Invalid input delay: {}", text))?;
+            },
+            "--cheats" => parsed.cheats = true,
+            "--no-watch" => parsed.watch = false,
+            "--keep-state" => parsed.keep_state = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    if parsed.record.is_some() && parsed.play.is_some() {
+        return Err("--record and --play can't be used together".to_string());
+    }
+    if parsed.peer.is_some() && (parsed.record.is_some() || parsed.play.is_some()) {
+        return Err("movies can't be recorded or played during netplay".to_string());
+    }
+    // a changed byte would desync a movie or the other player
+    if parsed.cheats && (parsed.peer.is_some() || parsed.record.is_some() || parsed.play.is_some()) {
+        return Err("cheats can't be used with movies or netplay".to_string());
+    }
+    parsed.rom = rom.ok_or("No game given")?;
+    Ok(parsed)
+}
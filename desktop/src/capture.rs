@@ -0,0 +1,115 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::time::{SystemTime, UNIX_EPOCH};
+use chip8_core::{FrameStream, GifRecorder, Renderer, StreamFormat};
+
+// recordings are made at a fixed size rather than the window's,
+// so clips come out the same whatever the window looks like
+const RECORD_SCALE: usize = 4;
+
+// file name like "screenshot-20261018-221530.png", in UTC
+pub fn timestamped(prefix: &str, extension: &str) -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{}-{:04}{:02}{:02}-{:02}{:02}{:02}.{}",
+        prefix, year, month, day,
+        rest / 3600, rest / 60 % 60, rest % 60,
+        extension,
+    )
+}
+
+// days since 1970-01-01 to a (year, month, day) date, Howard Hinnant's algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+// writes what is on screen right now to a PNG in the working directory
+pub fn save_screenshot(renderer: &Renderer) {
+    let name = timestamped("screenshot", "png");
+    match fs::write(&name, renderer.frame_png()) {
+        Ok(()) => println!("Saved {}", name),
+        Err(e) => println!("Unable to save {}: {}", name, e),
+    }
+}
+
+// gameplay clips, started and stopped with hotkeys
+// both kinds can run at once, and each uses the palette that was on when it started
+#[derive(Default)]
+pub struct Recorder {
+    gif: Option<GifRecorder>,
+    video: Option<(String, FrameStream<BufWriter<File>>)>,
+}
+
+impl Recorder {
+    // starts a GIF, or saves the one being recorded
+    pub fn toggle_gif(&mut self, renderer: &Renderer) {
+        match self.gif.take() {
+            Some(gif) => {
+                let name = timestamped("recording", "gif");
+                match fs::write(&name, gif.finish()) {
+                    Ok(()) => println!("Saved {} ({} frames)", name, gif.frame_count()),
+                    Err(e) => println!("Unable to save {}: {}", name, e),
+                }
+            },
+            None => {
+                self.gif = Some(GifRecorder::new(RECORD_SCALE, *renderer.palette()));
+                println!("Recording GIF");
+            },
+        }
+    }
+    // starts streaming frames to a Y4M file, or closes the one being written
+    pub fn toggle_video(&mut self, renderer: &Renderer) {
+        match self.video.take() {
+            Some((name, video)) => {
+                let frames = video.frame_count();
+                match video.finish() {
+                    Ok(_) => println!("Saved {} ({} frames)", name, frames),
+                    Err(e) => println!("Unable to save {}: {}", name, e),
+                }
+            },
+            None => {
+                let name = timestamped("recording", "y4m");
+                match File::create(&name) {
+                    Ok(file) => {
+                        let out = BufWriter::new(file);
+                        self.video = Some((name, FrameStream::new(out, StreamFormat::Y4m, RECORD_SCALE, *renderer.palette())));
+                        println!("Recording video");
+                    },
+                    Err(e) => println!("Unable to create {}: {}", name, e),
+                }
+            },
+        }
+    }
+    // call once per frame with the emulator's display
+    pub fn capture(&mut self, screen: &[bool]) {
+        if let Some(gif) = &mut self.gif {
+            gif.capture(screen);
+        }
+        if let Some((name, video)) = &mut self.video {
+            if let Err(e) = video.write_frame(screen) {
+                println!("Unable to write {}, stopped recording: {}", name, e);
+                self.video = None;
+            }
+        }
+    }
+    // saves whatever is still being recorded, when the emulator quits
+    pub fn finish(&mut self, renderer: &Renderer) {
+        if self.gif.is_some() {
+            self.toggle_gif(renderer);
+        }
+        if self.video.is_some() {
+            self.toggle_video(renderer);
+        }
+    }
+}
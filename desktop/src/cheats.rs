@@ -0,0 +1,147 @@
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use chip8_core::{parse_address, parse_bytes, Cheat, Cheats, Compare, Emu, MemorySearch};
+
+// where each game's cheats are kept, one file per ROM hash
+const CHEATS_DIR: &str = "cheats";
+// how many search results get printed
+const SHOWN_CANDIDATES: usize = 20;
+
+pub const HELP: &str = "\
+Cheat commands:
+    search                 start a new search, every address is a candidate
+    equal, changed, increased, decreased
+                           keep the addresses that did that since the last command
+    value N                keep the addresses holding N (decimal, or hex with 0x)
+    results                list what's left of the search
+    freeze ADDR [NAME]     hold the byte at ADDR at its current value
+    patch ADDR BYTES [NAME]
+                           write hex BYTES at ADDR every frame, e.g. patch 2a4 6e00
+    list                   list the cheats
+    on N, off N, remove N  turn cheat N on or off, or delete it
+    help                   show this";
+
+// a cheat console on the terminal, commands are typed while the game runs
+// stdin is read on its own thread so the game loop never waits on it
+pub struct CheatConsole {
+    lines: Receiver<String>,
+    cheats: Cheats,
+    search: Option<MemorySearch>,
+    path: PathBuf,
+}
+
+impl CheatConsole {
+    // loads the game's saved cheats, if any, and starts listening for commands
+    pub fn new(rom_hash: u64) -> Result<Self, String> {
+        let path = PathBuf::from(CHEATS_DIR).join(format!("{:016x}.txt", rom_hash));
+        let cheats = match fs::read_to_string(&path) {
+            Ok(text) => Cheats::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Cheats::default(),
+            Err(e) => return Err(format!("Unable to open {}: {}", path.display(), e)),
+        };
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        println!("{}", HELP);
+        if !cheats.list().is_empty() {
+            println!("Loaded {} cheats from {}", cheats.list().len(), path.display());
+        }
+        Ok(CheatConsole { lines, cheats, search: None, path })
+    }
+    // runs the commands typed since the last frame, then writes the cheats into memory
+    // call once a frame, before the game runs
+    pub fn update(&mut self, emu: &mut Emu) {
+        while let Ok(line) = self.lines.try_recv() {
+            if let Err(e) = self.run(line.trim(), emu) {
+                println!("{}", e);
+            }
+        }
+        self.cheats.apply(emu);
+    }
+
+    fn run(&mut self, line: &str, emu: &Emu) -> Result<(), String> {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        match command {
+            "" => (),
+            "help" => println!("{}", HELP),
+            "search" => {
+                self.search = Some(MemorySearch::new(emu));
+                println!("Searching all memory, now change the value in the game");
+            },
+            "equal" | "changed" | "increased" | "decreased" | "value" => {
+                let name = if command == "value" { rest } else { command };
+                let compare = Compare::named(name).ok_or_else(|| format!("Invalid value: {}", rest))?;
+                let search = self.search.as_mut().ok_or("No search running, start one with search")?;
+                let left = search.narrow(emu, compare);
+                println!("{} addresses left", left);
+                if left <= SHOWN_CANDIDATES {
+                    self.show_results(emu);
+                }
+            },
+            "results" => self.show_results(emu),
+            "freeze" => {
+                let (addr, name) = rest.split_once(' ').unwrap_or((rest, ""));
+                let addr = parse_address(addr)?;
+                let value = emu.read_ram(addr, 1).unwrap_or_default().to_vec();
+                self.add(Cheat::new(addr, value, name)?)?;
+            },
+            "patch" => {
+                let mut parts = rest.splitn(3, ' ');
+                let addr = parse_address(parts.next().unwrap_or_default())?;
+                let bytes = parse_bytes(parts.next().unwrap_or_default())?;
+                self.add(Cheat::new(addr, bytes, parts.next().unwrap_or_default())?)?;
+            },
+            "list" => {
+                for (n, cheat) in self.cheats.list().iter().enumerate() {
+                    println!("{}: {}", n, cheat);
+                }
+            },
+            "on" | "off" | "remove" => {
+                let idx = rest.parse().map_err(|_| format!("Invalid cheat number: {}", rest))?;
+                if command == "remove" {
+                    self.cheats.remove(idx).ok_or_else(|| format!("There is no cheat {}", idx))?;
+                } else {
+                    self.cheats.set_enabled(idx, command == "on")?;
+                }
+                self.save()?;
+            },
+            _ => return Err(format!("Unknown command: {}, try help", command)),
+        }
+        Ok(())
+    }
+
+    fn show_results(&self, emu: &Emu) {
+        let Some(search) = &self.search else {
+            println!("No search running, start one with search");
+            return;
+        };
+        for &addr in search.candidates().iter().take(SHOWN_CANDIDATES) {
+            let value = emu.read_ram(addr, 1).map_or(0, |byte| byte[0]);
+            println!("{:#05x} = {} ({:#04x})", addr, value, value);
+        }
+        if search.candidates().len() > SHOWN_CANDIDATES {
+            println!("and {} more", search.candidates().len() - SHOWN_CANDIDATES);
+        }
+    }
+
+    fn add(&mut self, cheat: Cheat) -> Result<(), String> {
+        println!("{}: {}", self.cheats.list().len(), cheat);
+        self.cheats.add(cheat);
+        self.save()
+    }
+    // every change goes straight to disk, so nothing is lost if the window is closed
+    fn save(&self) -> Result<(), String> {
+        fs::create_dir_all(CHEATS_DIR).map_err(|e| format!("Unable to create {}: {}", CHEATS_DIR, e))?;
+        fs::write(&self.path, self.cheats.to_string())
+            .map_err(|e| format!("Unable to save {}: {}", self.path.display(), e))
+    }
+}
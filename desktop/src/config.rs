@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+use sdl2::keyboard::Scancode;
+use chip8_core::{DisplayMode, Filter, Keymap, Palette, RomDb, RomProfile, DEFAULT_DECAY};
+use crate::gamepad::GamepadConfig;
+
+// default config file, read from the working directory if it exists
+pub const DEFAULT_CONFIG: &str = "chip8.toml";
+
+// user settings read from a TOML file, everything is optional
+// palette = "mine"
+// display = "decay"
+// decay = 0.3
+// filter = "scale3x"
+// [keymap]
+// KeyQ = 0x4
+// ArrowUp = 0x5
+// [gamepad.player1]
+// a = 0x6
+// [roms.624b3eed64313f42]
+// ticks_per_frame = 8
+// [palettes]
+// mine = ["#000000", "#ff0000", "#00ff00", "#0000ff"]
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // bindings layered on top of the default keymap
+    pub keymap: Keymap,
+    // controller bindings, see gamepad.rs
+    pub gamepad: GamepadConfig,
+    // per-game settings keyed by ROM hash, merged over the bundled database
+    pub roms: RomDb,
+    // palette to start with, a built-in name or one from [palettes]
+    pub palette: String,
+    // user-defined palettes, shown after the built-in ones
+    pub palettes: BTreeMap<String, Palette>,
+    // anti-flicker mode to start with, and how fast pixels fade in decay mode
+    pub display: DisplayMode,
+    pub decay: f32,
+    // upscaling filter to start with
+    pub filter: Filter,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            keymap: Keymap::empty(),
+            gamepad: GamepadConfig::default(),
+            roms: RomDb::default(),
+            palette: "classic".to_string(),
+            palettes: BTreeMap::new(),
+            display: DisplayMode::Raw,
+            decay: DEFAULT_DECAY,
+            filter: Filter::Nearest,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&text)
+            .map_err(|e| format!("Unable to parse {}: {}", path.display(), e))?;
+        config.keymap.validate()?;
+        config.gamepad.validate()?;
+        config.roms.validate()?;
+        if !config.palettes().iter().any(|(name, _)| *name == config.palette) {
+            return Err(format!("Unknown palette: {}", config.palette));
+        }
+        Ok(config)
+    }
+    // the bundled game database with the user's entries merged in
+    pub fn rom_db(&self) -> RomDb {
+        let mut db = RomDb::bundled();
+        db.merge(&self.roms);
+        db
+    }
+    // every palette to cycle through, built-in ones first
+    pub fn palettes(&self) -> Vec<(String, Palette)> {
+        let built_in = Palette::names().map(|name| (name.to_string(), Palette::named(name).unwrap()));
+        let custom = self.palettes.iter().map(|(name, palette)| (name.clone(), *palette));
+        built_in.chain(custom).collect()
+    }
+    // the game's keymap with the user's bindings on top
+    pub fn keymap(&self, profile: &RomProfile) -> Keymap {
+        let mut keymap = profile.keyboard();
+        keymap.merge(&self.keymap);
+        keymap
+    }
+}
+
+// converts a keymap key name (browser KeyboardEvent.code style) into an SDL scancode
+// scancodes are physical positions, so the 4x4 block stays put on any keyboard layout
+// SDL's own scancode names ("Left Shift", "Keypad 1", ...) are accepted too
+pub fn code_to_scancode(code: &str) -> Option<Scancode> {
+    let name = if let Some(letter) = code.strip_prefix("Key") {
+        letter.to_string()
+    } else if let Some(digit) = code.strip_prefix("Digit") {
+        digit.to_string()
+    } else if let Some(digit) = code.strip_prefix("Numpad") {
+        format!("Keypad {}", digit)
+    } else if let Some(dir) = code.strip_prefix("Arrow") {
+        dir.to_string()
+    } else {
+        match code {
+            "Enter" => "Return".to_string(),
+            "ShiftLeft" => "Left Shift".to_string(),
+            "ShiftRight" => "Right Shift".to_string(),
+            "ControlLeft" => "Left Ctrl".to_string(),
+            "ControlRight" => "Right Ctrl".to_string(),
+            _ => code.to_string(),
+        }
+    };
+    Scancode::from_name(&name)
+}
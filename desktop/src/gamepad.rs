@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::Deserialize;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+use chip8_core::{Keymap, RomProfile};
+use crate::keypad::Keypad;
+
+// two controllers is enough for every two-player game in c8games (PONG, PONG2, ...)
+pub const MAX_PLAYERS: usize = 2;
+// how far the left stick has to move before it counts as a d-pad press
+const STICK_THRESHOLD: i16 = 16_000;
+// stick directions, in the order they are tracked
+const STICK_DIRS: [&str; 4] = ["dpup", "dpdown", "dpleft", "dpright"];
+
+// controller bindings for both players, everything is optional
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PadBindings {
+    pub player1: Keymap,
+    pub player2: Keymap,
+}
+
+impl Default for PadBindings {
+    fn default() -> Self {
+        Self { player1: Keymap::empty(), player2: Keymap::empty() }
+    }
+}
+
+impl PadBindings {
+    fn player(&self, idx: usize) -> &Keymap {
+        if idx == 0 { &self.player1 } else { &self.player2 }
+    }
+}
+
+// [gamepad] section of the config file
+// [gamepad.player1]         applies to every game
+// [gamepad.roms.PONG2]      only applies to the ROM with that file name
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct GamepadConfig {
+    #[serde(flatten)]
+    pub all: PadBindings,
+    pub roms: BTreeMap<String, PadBindings>,
+}
+
+impl GamepadConfig {
+    // final keymap for each player when playing rom_name
+    // built up from the game's profile, user settings, and finally the user's per-ROM settings
+    pub fn keymaps(&self, rom_name: &str, profile: &RomProfile) -> [Keymap; MAX_PLAYERS] {
+        let mut maps = [profile.gamepad(0), profile.gamepad(1)];
+        for (idx, map) in maps.iter_mut().enumerate() {
+            map.merge(self.all.player(idx));
+            if let Some(rom) = self.roms.get(rom_name) {
+                map.merge(rom.player(idx));
+            }
+        }
+        maps
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let all = std::iter::once(&self.all).chain(self.roms.values());
+        for pads in all {
+            pads.player1.validate()?;
+            pads.player2.validate()?;
+        }
+        Ok(())
+    }
+}
+
+// connected controllers, one per player
+// controllers are handed out to players in the order they are plugged in,
+// and can come and go while the game is running
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    slots: [Option<GameController>; MAX_PLAYERS],
+    maps: [Keymap; MAX_PLAYERS],
+    // buttons held down, by their SDL names
+    buttons: [BTreeSet<String>; MAX_PLAYERS],
+    // which left stick directions are currently pushed past the threshold
+    stick: [[bool; 4]; MAX_PLAYERS],
+}
+
+impl Gamepads {
+    pub fn new(subsystem: GameControllerSubsystem, maps: [Keymap; MAX_PLAYERS]) -> Self {
+        Self { subsystem, slots: [None, None], maps, buttons: Default::default(), stick: [[false; 4]; MAX_PLAYERS] }
+    }
+    // handles controller events, returns false for anything that isn't one
+    // SDL also sends a device added event for controllers plugged in before startup
+    pub fn handle_event(&mut self, evt: &Event, keypad: &mut Keypad) -> bool {
+        match *evt {
+            Event::ControllerDeviceAdded{which, ..} => self.connect(which),
+            Event::ControllerDeviceRemoved{which, ..} => self.disconnect(which, keypad),
+            Event::ControllerButtonDown{which, button, ..} => self.button(which, button, true, keypad),
+            Event::ControllerButtonUp{which, button, ..} => self.button(which, button, false, keypad),
+            Event::ControllerAxisMotion{which, axis, value, ..} => self.axis(which, axis, value, keypad),
+            _ => return false,
+        }
+        true
+    }
+    // which player a controller belongs to, from its instance id
+    fn player(&self, id: u32) -> Option<usize> {
+        self.slots.iter().position(|slot| {
+            slot.as_ref().is_some_and(|pad| pad.instance_id() == id)
+        })
+    }
+
+    fn connect(&mut self, joystick_idx: u32) {
+        let free = match self.slots.iter().position(|slot| slot.is_none()) {
+            Some(free) => free,
+            // every player already has a controller
+            None => return,
+        };
+        match self.subsystem.open(joystick_idx) {
+            Ok(pad) => {
+                println!("Player {} controller: {}", free + 1, pad.name());
+                self.slots[free] = Some(pad);
+            },
+            Err(e) => println!("Unable to open controller: {}", e),
+        }
+    }
+    // lets go of everything the controller was holding so no key gets stuck down
+    fn disconnect(&mut self, id: u32, keypad: &mut Keypad) {
+        if let Some(player) = self.player(id) {
+            self.buttons[player].clear();
+            self.stick[player] = [false; 4];
+            keypad.set_pad(player, 0);
+            self.slots[player] = None;
+            println!("Player {} controller disconnected", player + 1);
+        }
+    }
+
+    fn button(&mut self, id: u32, button: Button, pressed: bool, keypad: &mut Keypad) {
+        if let Some(player) = self.player(id) {
+            if pressed {
+                self.buttons[player].insert(button.string());
+            } else {
+                self.buttons[player].remove(&button.string());
+            }
+            keypad.set_pad(player, self.mask(player));
+        }
+    }
+    // the left stick doubles as the d-pad
+    fn axis(&mut self, id: u32, axis: Axis, value: i16, keypad: &mut Keypad) {
+        let player = match self.player(id) {
+            Some(player) => player,
+            None => return,
+        };
+        // (negative direction, positive direction) as indexes into STICK_DIRS
+        let (neg, pos) = match axis {
+            Axis::LeftY => (0, 1),
+            Axis::LeftX => (2, 3),
+            _ => return,
+        };
+        self.stick[player][neg] = value < -STICK_THRESHOLD;
+        self.stick[player][pos] = value > STICK_THRESHOLD;
+        keypad.set_pad(player, self.mask(player));
+    }
+    // the keys a player's controller holds, worked out from everything pressed on it
+    // so the stick and the d-pad can both hold the same key without one releasing the other
+    fn mask(&self, player: usize) -> u16 {
+        let stick = STICK_DIRS.iter().zip(self.stick[player]).filter(|&(_, pushed)| pushed).map(|(&dir, _)| dir);
+        self.buttons[player]
+            .iter()
+            .map(String::as_str)
+            .chain(stick)
+            .filter_map(|name| self.maps[player].lookup(name))
+            .fold(0, |mask, k| mask | 1 << k)
+    }
+}
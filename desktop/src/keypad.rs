@@ -0,0 +1,31 @@
+use crate::gamepad::MAX_PLAYERS;
+
+// the chip-8 keys the local player is holding, from the keyboard and controllers together
+// each device's keys are kept apart and only put together in mask(), so letting go of a key
+// on one device doesn't release it while another is still holding it down
+// handed to the emulator once per frame rather than as events arrive,
+// so movies and netplay can decide what the emulator actually sees
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Keypad {
+    keyboard: u16,
+    pads: [u16; MAX_PLAYERS],
+}
+
+impl Keypad {
+    // a key on the keyboard
+    pub fn press(&mut self, key: usize, pressed: bool) {
+        if pressed {
+            self.keyboard |= 1 << key;
+        } else {
+            self.keyboard &= !(1 << key);
+        }
+    }
+    // everything a player's controller is holding, 0 once it's unplugged
+    pub fn set_pad(&mut self, player: usize, mask: u16) {
+        self.pads[player] = mask;
+    }
+    // bit n = key n, the same as Emu::key_mask
+    pub fn mask(&self) -> u16 {
+        self.pads.iter().fold(self.keyboard, |mask, pad| mask | pad)
+    }
+}
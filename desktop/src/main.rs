@@ -1,131 +1,381 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use std::path::Path;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
 use sdl2::event::Event;
 use chip8_core::*;
 use std::env;
 
-// using scale for modern computers
-const SCALE: u32 = 15;
-// importing the public constants from chip8_core and scaling them accordingly
-const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * SCALE;
-const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * SCALE;
+mod args;
+mod capture;
+mod cheats;
+mod config;
+mod gamepad;
+mod keypad;
+mod movie;
+mod netplay;
+mod reload;
+use cheats::CheatConsole;
+use config::Config;
+use gamepad::Gamepads;
+use keypad::Keypad;
+use movie::MovieSession;
+use netplay::Netplay;
+use reload::RomWatcher;
+
+// saves a screenshot to the working directory
+const SCREENSHOT_KEY: Keycode = Keycode::F12;
+// starts and stops recording a GIF
+const GIF_KEY: Keycode = Keycode::F9;
+// starts and stops recording a Y4M video
+const VIDEO_KEY: Keycode = Keycode::F10;
+// toggles fullscreen, Alt+Enter does too
+const FULLSCREEN_KEY: Keycode = Keycode::F11;
+// cycles through the color palettes
+const PALETTE_KEY: Keycode = Keycode::F2;
+// cycles through the anti-flicker display modes
+const DISPLAY_MODE_KEY: Keycode = Keycode::F3;
+// cycles through the upscaling filters
+const FILTER_KEY: Keycode = Keycode::F4;
+// prints the registers and the next instruction to the terminal
+const REGISTERS_KEY: Keycode = Keycode::F5;
+// loads the game from its file again and starts it over
+const RELOAD_KEY: Keycode = Keycode::F6;
 // how many ticks the emulator runs at before updating the display
-const TICK_PER_FRAME: usize = 5;
+// (unless the game's profile asks for something else)
+const TICK_PER_FRAME: u32 = 5;
 
-// converts a Keycode and output a u8 value
-fn key2btn(key: Keycode) -> Option<usize> {
-    // keyboard in a 4x4 layout
-    match key {
-        Keycode::Num1 =>    Some(0x1),
-        Keycode::Num2 =>    Some(0x2),
-        Keycode::Num3 =>    Some(0x3),
-        Keycode::Num4 =>    Some(0xC),
-        Keycode::Q =>       Some(0x4),
-        Keycode::W =>       Some(0x5),
-        Keycode::E =>       Some(0x6),
-        Keycode::R =>       Some(0xD),
-        Keycode::A =>       Some(0x7),
-        Keycode::S =>       Some(0x8),
-        Keycode::D =>       Some(0x9),
-        Keycode::F =>       Some(0xE),
-        Keycode::Z =>       Some(0xA),
-        Keycode::X =>       Some(0x0),
-        Keycode::C =>       Some(0xB),
-        Keycode::V =>       Some(0xF),
-        _ =>                None, 
+// builds the scancode lookup table the event loop uses from a keymap
+// names SDL doesn't know about are reported and skipped
+fn scancode_table(keymap: &Keymap) -> HashMap<Scancode, usize> {
+    let mut table = HashMap::new();
+    for (code, key) in keymap.bindings() {
+        match config::code_to_scancode(code) {
+            Some(scancode) => { table.insert(scancode, key as usize); },
+            None => println!("Unknown key in keymap: {}", code),
+        }
     }
+    table
+}
+
+// where the display goes in a window of the given size, centered with bars around it
+// whole-number scaling keeps every chip-8 pixel the same size, fit uses all the room it can
+// keeping the display's shape, returns the area and the scale to render at
+fn layout(window: (u32, u32), display: (u32, u32), fit: bool) -> (Rect, u32) {
+    let (win_w, win_h) = window;
+    let (disp_w, disp_h) = display;
+    let (w, h) = if fit {
+        // whichever side runs out of room first decides the size
+        if win_w * disp_h <= win_h * disp_w {
+            (win_w, win_w * disp_h / disp_w)
+        } else {
+            (win_h * disp_w / disp_h, win_h)
+        }
+    } else {
+        let scale = (win_w / disp_w).min(win_h / disp_h).max(1);
+        (disp_w * scale, disp_h * scale)
+    };
+    let x = (win_w as i32 - w as i32) / 2;
+    let y = (win_h as i32 - h as i32) / 2;
+    // the renderer's scale is the largest whole number that fits, SDL stretches the rest
+    (Rect::new(x, y, w.max(1), h.max(1)), (w / disp_w).max(1))
 }
 
 // self explanatory
-// we get our screen buffer array and iterate accross it
-// if we find a white pixel (aka. set to true), calculate the x,y values of the screen
-// draw a rectangle there, scaled up
-fn draw_screen(emu: &Emu, canvas: &mut Canvas<Window>) {
-    // clear the canvas as black
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+// the core renders the whole display into an RGBA image, colors and effects included
+// we upload it into the texture in one go and copy it into the window, letterboxed
+// the layout is worked out every frame so resizing the window or going fullscreen just works
+fn draw_screen<'a>(
+    renderer: &mut Renderer,
+    emu: &Emu,
+    texture: &mut Texture<'a>,
+    creator: &'a TextureCreator<WindowContext>,
+    canvas: &mut Canvas<Window>,
+    fit: bool,
+) {
+    let display = (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    let (dest, scale) = layout(canvas.output_size().unwrap(), display, fit);
+    renderer.set_scale(scale as usize);
+    // the texture has to match the renderer's image size
+    let (width, height) = (renderer.width() as u32, renderer.height() as u32);
+    let query = texture.query();
+    if (query.width, query.height) != (width, height) {
+        *texture = creator
+            .create_texture_streaming(PixelFormatEnum::RGBA32, width, height)
+            .unwrap();
+    }
+    let pitch = renderer.pitch();
+    let frame = renderer.render(emu.get_display());
+    texture.update(None, frame, pitch).unwrap();
+    // bars in the background color
+    let bg = renderer.palette().background();
+    canvas.set_draw_color(Color::RGB(bg.0, bg.1, bg.2));
     canvas.clear();
+    canvas.copy(texture, None, dest).unwrap();
+    canvas.present();
+}
 
-    let screen_buf = emu.get_display();
-    // set the draw color to white
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
-    // iterate through each point and see if it should be drawn
-    for (i, pixel) in screen_buf.iter().enumerate() {
-        if *pixel {
-            // convert the array's index into a 2D (x,y) position
-            let x = (i % SCREEN_WIDTH) as u32;
-            let y = (i / SCREEN_WIDTH) as u32;
-            // draw a rectangle at (x, y) scaled to SCALE value
-            let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
-            canvas.fill_rect(rect).unwrap();
-        }
+fn toggle_fullscreen(canvas: &mut Canvas<Window>) {
+    let window = canvas.window_mut();
+    let mode = match window.fullscreen_state() {
+        FullscreenType::Off => FullscreenType::Desktop,
+        _ => FullscreenType::Off,
+    };
+    if let Err(e) = window.set_fullscreen(mode) {
+        println!("Unable to switch fullscreen: {}", e);
     }
-    canvas.present();
 }
 
 fn main() {
     // get cli parameters
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: cargo run path/to/game");
+    let args = match args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}\n{}", e, args::USAGE);
+            return;
+        }
+    };
+    let rom_path = &args.rom;
+    // user settings, an explicit --config has to exist but the default file is optional
+    let config = match args.config.as_deref() {
+        Some(path) => Config::load(path),
+        None if Path::new(config::DEFAULT_CONFIG).exists() => Config::load(Path::new(config::DEFAULT_CONFIG)),
+        None => Ok(Config::default()),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    // emulator object
+    let mut chip8 = Emu::new();
+    // attempt to read file, if it exists
+    let mut rom = File::open(rom_path).expect("Unable to open file");
+    let mut buffer = Vec::new();
+    rom.read_to_end(&mut buffer).unwrap();
+    if let Err(e) = chip8.load(&buffer) {
+        println!("Unable to load {}: {}", rom_path, e);
         return;
     }
+    // look the game up by its hash to pick its quirks, speed and controls
+    let mut profile = config.rom_db().lookup(chip8.rom_hash()).cloned().unwrap_or_default();
+    // unknown games get a guess from scanning their code
+    if profile.quirks().is_none() {
+        let analysis = analyze(chip8.program());
+        println!(
+            "Looks like a {} game ({:.0}% sure){}",
+            analysis.platform.name(),
+            analysis.confidence * 100.0,
+            if analysis.is_confident() { "" } else { ", keeping the default quirks" },
+        );
+        profile.fill_from(&analysis);
+    }
+    if let Some(quirks) = profile.quirks() {
+        chip8.set_quirks(quirks);
+    }
+    let mut ticks_per_frame = profile.ticks_per_frame.unwrap_or(TICK_PER_FRAME);
+    // movies start from a freshly loaded game, and playback brings its own settings
+    let mut movie = match (&args.record, &args.play) {
+        (Some(path), _) => Some(MovieSession::record(path, &chip8, ticks_per_frame)),
+        (_, Some(path)) => match MovieSession::play(path, &mut chip8, &buffer) {
+            Ok((session, ticks)) => {
+                ticks_per_frame = ticks;
+                Some(session)
+            },
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        },
+        _ => None,
+    };
+    // netplay waits for the other player before opening the window
+    let mut netplay = match &args.peer {
+        Some(peer) => {
+            let owned = netplay::owned_keys(&profile);
+            match Netplay::connect(&args.bind, peer, args.player, args.input_delay, owned, ticks_per_frame, &mut chip8, &buffer) {
+                Ok(netplay) => Some(netplay),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        },
+        None => None,
+    };
+    let mut cheats = None;
+    if args.cheats {
+        match CheatConsole::new(chip8.rom_hash()) {
+            Ok(console) => cheats = Some(console),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+    }
+    // a new build of the game is picked up as soon as it's written, but a movie or the other
+    // player would fall out of step with it
+    let mut watcher = (movie.is_none() && netplay.is_none()).then(|| RomWatcher::new(rom_path, args.keep_state));
+    let keys = scancode_table(&config.keymap(&profile));
+    let palettes = config.palettes();
+    let mut palette_idx = palettes.iter().position(|(name, _)| *name == config.palette).unwrap();
+    // drawn at the window's size, so the filters have room to work
+    let mut renderer = Renderer::new(args.scale as usize);
+    renderer.set_palette(palettes[palette_idx].1);
+    renderer.set_display_mode(config.display);
+    renderer.set_decay(config.decay);
+    renderer.set_filter(config.filter);
+    if let Some(help) = &profile.key_help {
+        println!("Controls: {}", help);
+    }
+    let title = match &profile.title {
+        Some(name) => format!("Chip-8 Emulator - {}", name),
+        None => "Chip-8 Emulator".to_string(),
+    };
 
     // seting up SDL
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("Chip-8 Emulator", WINDOW_WIDTH, WINDOW_HEIGHT)
+    let mut window = video_subsystem
+        .window(&title, renderer.width() as u32, renderer.height() as u32)
         .position_centered()
+        .resizable()
         .opengl()
         .build()
         .unwrap();
+    if args.fullscreen {
+        window.set_fullscreen(FullscreenType::Desktop).unwrap();
+    }
     // we draw to the canvas with vsync on
     let mut canvas = window.into_canvas().present_vsync().build().unwrap();
     canvas.clear();
     canvas.present();
+    // the texture the rendered frames are uploaded into
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGBA32, renderer.width() as u32, renderer.height() as u32)
+        .unwrap();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
-    // emulator object
-    let mut chip8 = Emu::new();
-    // attempt to read file, if it exists
-    let mut rom = File::open(&args[1]).expect("Unable to open file");
-    let mut buffer = Vec::new();
-    rom.read_to_end(&mut buffer).unwrap();
-    chip8.load(&buffer);
+    // controllers are picked up as they get plugged in
+    // per-ROM layouts are looked up by the game's file name
+    let rom_name = Path::new(rom_path).file_name().unwrap_or_default().to_string_lossy();
+    let mut pads = Gamepads::new(
+        sdl_context.game_controller().unwrap(),
+        config.gamepad.keymaps(&rom_name, &profile),
+    );
+    let mut recorder = capture::Recorder::default();
+    // keys held on the keyboard and controllers, given to the emulator every frame
+    let mut keypad = Keypad::default();
     // loop for the program
     'gameloop: loop {
         for evt in event_pump.poll_iter() {
+            if pads.handle_event(&evt, &mut keypad) {
+                continue;
+            }
             match evt {
                 // break in case the user exits the program
                 // or if the user presses the ESC key
                 Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
                     break 'gameloop;
                 },
+                Event::KeyDown{keycode: Some(SCREENSHOT_KEY), repeat: false, ..} => {
+                    capture::save_screenshot(&renderer);
+                },
+                Event::KeyDown{keycode: Some(GIF_KEY), repeat: false, ..} => {
+                    recorder.toggle_gif(&renderer);
+                },
+                Event::KeyDown{keycode: Some(VIDEO_KEY), repeat: false, ..} => {
+                    recorder.toggle_video(&renderer);
+                },
+                Event::KeyDown{keycode: Some(FULLSCREEN_KEY), repeat: false, ..} => {
+                    toggle_fullscreen(&mut canvas);
+                },
+                Event::KeyDown{keycode: Some(Keycode::Return), keymod, repeat: false, ..}
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    toggle_fullscreen(&mut canvas);
+                },
+                Event::KeyDown{keycode: Some(PALETTE_KEY), repeat: false, ..} => {
+                    palette_idx = (palette_idx + 1) % palettes.len();
+                    renderer.set_palette(palettes[palette_idx].1);
+                    println!("Palette: {}", palettes[palette_idx].0);
+                },
+                Event::KeyDown{keycode: Some(DISPLAY_MODE_KEY), repeat: false, ..} => {
+                    let modes = DisplayMode::ALL;
+                    let idx = modes.iter().position(|&m| m == renderer.display_mode()).unwrap();
+                    renderer.set_display_mode(modes[(idx + 1) % modes.len()]);
+                    println!("Display mode: {}", renderer.display_mode().name());
+                },
+                Event::KeyDown{keycode: Some(FILTER_KEY), repeat: false, ..} => {
+                    let filters = Filter::ALL;
+                    let idx = filters.iter().position(|&f| f == renderer.filter()).unwrap();
+                    renderer.set_filter(filters[(idx + 1) % filters.len()]);
+                    println!("Filter: {}", renderer.filter().name());
+                },
+                Event::KeyDown{keycode: Some(REGISTERS_KEY), repeat: false, ..} => {
+                    let cpu = chip8.cpu();
+                    let opcode = chip8.read_ram(cpu.pc, 2).map_or(0, |op| (op[0] as u16) << 8 | op[1] as u16);
+                    println!("{}\nnext {:04x}", cpu, opcode);
+                },
+                Event::KeyDown{keycode: Some(RELOAD_KEY), repeat: false, ..} => {
+                    match &mut watcher {
+                        Some(watcher) => watcher.reload(&mut chip8),
+                        None => println!("Reloading is off during netplay and movies"),
+                    }
+                },
                 // sets the key press to true
-                Event::KeyDown{keycode: Some(key), ..} => {
-                    if let Some(k) = key2btn(key) {
-                        chip8.keypress(k, true);
+                // scancodes rather than keycodes, so the keypad sits in the same place on any layout
+                Event::KeyDown{scancode: Some(sc), ..} => {
+                    if let Some(&k) = keys.get(&sc) {
+                        keypad.press(k, true);
                     }
                 },
                 // sets the key up to false
-                Event::KeyUp{keycode: Some(key), ..} => {
-                    if let Some(k) = key2btn(key) {
-                        chip8.keypress(k, false);
+                Event::KeyUp{scancode: Some(sc), ..} => {
+                    if let Some(&k) = keys.get(&sc) {
+                        keypad.press(k, false);
                     }
                 },
                 _ => ()
             }
         }
 
-        for _ in 0..TICK_PER_FRAME {
-            chip8.tick();
+        if args.watch {
+            if let Some(watcher) = &mut watcher {
+                watcher.poll(&mut chip8);
+            }
         }
-        chip8.tick_timers();
-        draw_screen(&chip8, &mut canvas);
+        if let Some(netplay) = &mut netplay {
+            netplay.advance(&mut chip8, keypad.mask());
+        } else {
+            chip8.set_key_mask(keypad.mask());
+            if let Some(movie) = &mut movie {
+                movie.before_frame(&mut chip8);
+            }
+            if let Some(cheats) = &mut cheats {
+                cheats.update(&mut chip8);
+            }
+            for _ in 0..ticks_per_frame {
+                chip8.tick();
+            }
+            chip8.tick_timers();
+            if let Some(movie) = &mut movie {
+                movie.after_frame(&chip8);
+            }
+        }
+        recorder.capture(chip8.get_display());
+        draw_screen(&mut renderer, &chip8, &mut texture, &texture_creator, &mut canvas, args.fit);
+    }
+    recorder.finish(&renderer);
+    if let Some(movie) = movie {
+        movie.finish();
     }
 }
@@ -0,0 +1,63 @@
+use std::fs;
+use chip8_core::{Emu, Movie};
+
+// a movie being recorded or played back alongside the game loop
+pub enum MovieSession {
+    // the keys held every frame are written to path when the emulator quits
+    Recording { path: String, movie: Movie },
+    // the movie drives the keypad until it runs out, then the player takes over
+    Playing { movie: Movie, frame: usize, desynced: bool },
+}
+
+impl MovieSession {
+    // starts recording, call once the game is loaded and set up
+    pub fn record(path: &str, emu: &Emu, ticks_per_frame: u32) -> Self {
+        println!("Recording a movie to {}", path);
+        MovieSession::Recording { path: path.to_string(), movie: Movie::new(emu, ticks_per_frame) }
+    }
+    // loads a movie and sets the emulator up to play it, returns it with the speed it was recorded at
+    pub fn play(path: &str, emu: &mut Emu, rom: &[u8]) -> Result<(Self, u32), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to open {}: {}", path, e))?;
+        let movie = Movie::from_toml(&text)?;
+        movie.start(emu, rom)?;
+        let ticks_per_frame = movie.ticks_per_frame();
+        println!("Playing {} ({} frames)", path, movie.len());
+        Ok((MovieSession::Playing { movie, frame: 0, desynced: false }, ticks_per_frame))
+    }
+    // call before running a frame, after the player's keys are in, playback replaces them
+    pub fn before_frame(&mut self, emu: &mut Emu) {
+        if let MovieSession::Playing { movie, frame, .. } = self {
+            // past the end the player's own keys go through untouched
+            match movie.keys(*frame) {
+                Some(keys) => emu.set_key_mask(keys),
+                None if *frame == movie.len() => println!("Movie finished, you have control"),
+                None => (),
+            }
+        }
+    }
+    // call after running a frame
+    pub fn after_frame(&mut self, emu: &Emu) {
+        match self {
+            MovieSession::Recording { movie, .. } => movie.record(emu.key_mask(), emu),
+            MovieSession::Playing { movie, frame, desynced } => {
+                *frame += 1;
+                // one warning is enough, everything after a desync is off too
+                if !*desynced {
+                    if let Err(e) = movie.check(*frame, emu) {
+                        println!("Movie {}", e);
+                        *desynced = true;
+                    }
+                }
+            },
+        }
+    }
+    // saves a recording, when the emulator quits
+    pub fn finish(self) {
+        if let MovieSession::Recording { path, movie } = self {
+            match fs::write(&path, movie.to_toml()) {
+                Ok(()) => println!("Saved {} ({} frames)", path, movie.len()),
+                Err(e) => println!("Unable to save {}: {}", path, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use chip8_core::{Emu, RomProfile, SaveState, CHECKSUM_INTERVAL};
+
+// frames between pressing a key and the game seeing it, on both machines
+// enough to cover a LAN round trip without rolling back at all most of the time
+pub const DEFAULT_INPUT_DELAY: usize = 2;
+// how far we run ahead of the other player's input, guessing their keys, before waiting for them
+const MAX_ROLLBACK: usize = 8;
+// inputs are resent until acknowledged, so a lost packet is covered by the next one
+const MAX_INPUTS: usize = 64;
+// frames without hearing from the other player before saying so
+const STALL_WARNING: u32 = 120;
+// every packet starts with "C8N" and its kind
+const MAGIC: &[u8; 3] = b"C8N";
+const HELLO: u8 = 0;
+const INPUT: u8 = 1;
+
+// which keys each player controls, as key masks
+// games with a two-player controller layout in the database (PONG, PONG2) split the pad
+// between the players, anything else lets both press every key
+pub fn owned_keys(profile: &RomProfile) -> [u16; 2] {
+    match profile.gamepad.as_deref() {
+        Some([p1, p2, ..]) => {
+            let mask = |keymap: &chip8_core::Keymap| keymap.bindings().fold(0, |mask, (_, key)| mask | 1 << key);
+            [mask(p1), mask(p2)]
+        },
+        _ => [0xFFFF, 0xFFFF],
+    }
+}
+
+// one side of a two-player game over UDP
+// both emulators run the same frames with the same keys, so only inputs cross the network
+// the other player's keys are guessed (held the same as last time) when they're late, and
+// when the real ones turn out different we load the save state from that frame and
+// play the frames since again, which is what rollback means
+pub struct Netplay {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    player: usize,
+    owned: [u16; 2],
+    ticks_per_frame: u32,
+    hello: Vec<u8>,
+    // next frame to run
+    frame: usize,
+    // our keys for every frame, starting with input-delay empty frames
+    local: Vec<u16>,
+    // the other player's keys, only the frames we've heard about so far
+    remote: Vec<u16>,
+    // the other player's keys as used when each frame ran, real or guessed
+    predicted: Vec<u16>,
+    // state before each frame from first_state on, the ones we might have to go back to
+    states: VecDeque<SaveState>,
+    first_state: usize,
+    // earliest frame that ran with a wrong guess
+    rollback: Option<usize>,
+    // the other player has our keys for every frame before this
+    peer_ack: usize,
+    // state hashes every CHECKSUM_INTERVAL frames, ours and theirs, by frame
+    hashes: BTreeMap<usize, u64>,
+    peer_hashes: BTreeMap<usize, u64>,
+    desynced: bool,
+    stalled: u32,
+}
+
+impl Netplay {
+    // waits for the other player, then sets the emulator up the same way on both sides
+    // player 1's random seed is the one both use
+    #[allow(clippy::too_many_arguments)]
+    pub fn connect(
+        bind: &str,
+        peer: &str,
+        player: usize,
+        delay: usize,
+        owned: [u16; 2],
+        ticks_per_frame: u32,
+        emu: &mut Emu,
+        rom: &[u8],
+    ) -> Result<Self, String> {
+        let socket = UdpSocket::bind(bind).map_err(|e| format!("Unable to listen on {}: {}", bind, e))?;
+        let peer = peer
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| format!("Unable to find {}", peer))?;
+        let quirks = emu.quirks().bits();
+        let mut hello = packet(HELLO);
+        hello.extend_from_slice(&emu.rom_hash().to_le_bytes());
+        hello.extend_from_slice(&emu.seed().to_le_bytes());
+        hello.extend_from_slice(&ticks_per_frame.to_le_bytes());
+        hello.extend_from_slice(&[quirks, player as u8]);
+
+        println!("Player {}, waiting for the other player at {}", player + 1, peer);
+        socket.set_read_timeout(Some(Duration::from_millis(100))).map_err(|e| e.to_string())?;
+        let mut buf = [0u8; 512];
+        let theirs = loop {
+            // errors here are usually the other side not being up yet, keep trying
+            let _ = socket.send_to(&hello, peer);
+            if let Ok((len, from)) = socket.recv_from(&mut buf) {
+                if from == peer && len == 26 && buf[..4] == hello[..4] {
+                    break buf[..len].to_vec();
+                }
+            }
+        };
+        // let them know we're here too, in case they missed the last one
+        let _ = socket.send_to(&hello, peer);
+        let field = |at: usize| u64::from_le_bytes(theirs[at..at + 8].try_into().unwrap());
+        if field(4) != emu.rom_hash() {
+            return Err("The other player is running a different game".to_string());
+        }
+        if u32::from_le_bytes(theirs[20..24].try_into().unwrap()) != ticks_per_frame || theirs[24] != quirks {
+            return Err("The other player's game runs with different settings".to_string());
+        }
+        if theirs[25] as usize == player {
+            return Err(format!("Both sides are player {}, one of them should be the other", player + 1));
+        }
+        let seed = if player == 0 { emu.seed() } else { field(12) };
+        let rom_hash = emu.rom_hash();
+        emu.reset();
+        emu.set_seed(seed);
+        emu.load(rom)?;
+        debug_assert_eq!(emu.rom_hash(), rom_hash);
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        println!("Connected, you are player {}", player + 1);
+
+        Ok(Self {
+            socket,
+            peer,
+            player,
+            owned,
+            ticks_per_frame,
+            hello,
+            frame: 0,
+            local: vec![0; delay],
+            remote: Vec::new(),
+            predicted: Vec::new(),
+            states: VecDeque::new(),
+            first_state: 0,
+            rollback: None,
+            peer_ack: 0,
+            hashes: BTreeMap::new(),
+            peer_hashes: BTreeMap::new(),
+            desynced: false,
+            stalled: 0,
+        })
+    }
+    // call once per display frame instead of running the emulator directly
+    // usually runs one frame, more when rolling back, none when waiting on the other player
+    pub fn advance(&mut self, emu: &mut Emu, keys: u16) {
+        self.catch_up(emu);
+        self.compare_hashes();
+        if self.frame < self.remote.len() + MAX_ROLLBACK {
+            // our keys take effect after the input delay
+            self.local.push(keys & self.owned[self.player]);
+            self.step(emu);
+            self.stalled = 0;
+        } else {
+            self.stalled += 1;
+            if self.stalled == STALL_WARNING {
+                println!("Waiting for the other player...");
+            }
+        }
+        // we'll never go back to before the frames we have real input for
+        while self.first_state < self.remote.len().min(self.frame) {
+            self.states.pop_front();
+            self.first_state += 1;
+        }
+        self.send();
+    }
+
+    // takes in what the other player sent, and plays the frames we guessed wrong again
+    fn catch_up(&mut self, emu: &mut Emu) {
+        self.receive();
+        if let Some(from) = self.rollback.take() {
+            let idx = from - self.first_state;
+            emu.load_state(&self.states[idx]);
+            self.states.truncate(idx);
+            let to = self.frame;
+            self.frame = from;
+            while self.frame < to {
+                self.step(emu);
+            }
+        }
+    }
+
+    fn step(&mut self, emu: &mut Emu) {
+        let frame = self.frame;
+        self.states.push_back(emu.save_state());
+        // no news yet, guess they're still holding what they held last
+        let remote = self.remote.get(frame).or(self.remote.last()).copied().unwrap_or(0);
+        self.predicted.truncate(frame);
+        self.predicted.push(remote);
+        let peer = 1 - self.player;
+        emu.set_key_mask(self.local[frame] & self.owned[self.player] | remote & self.owned[peer]);
+        for _ in 0..self.ticks_per_frame {
+            emu.tick();
+        }
+        emu.tick_timers();
+        self.frame += 1;
+        if self.frame.is_multiple_of(CHECKSUM_INTERVAL) {
+            self.hashes.insert(self.frame, emu.state_hash());
+        }
+    }
+
+    fn receive(&mut self) {
+        let mut buf = [0u8; 512];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == self.peer && len >= 4 && buf[..3] == *MAGIC => match buf[3] {
+                    // they didn't get our hello before we started
+                    HELLO => { let _ = self.socket.send_to(&self.hello, self.peer); },
+                    INPUT => self.handle_input(&buf[..len]),
+                    _ => (),
+                },
+                Ok(_) => (),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // e.g. connection refused while the other side restarts, try again next frame
+                Err(_) => break,
+            }
+        }
+    }
+    // ack, first frame, count, that many key masks, then a frame and its state hash
+    fn handle_input(&mut self, data: &[u8]) {
+        let u32_at = |at: usize| data.get(at..at + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize);
+        let (ack, start, count) = match (u32_at(4), u32_at(8), data.get(12)) {
+            (Some(ack), Some(start), Some(&count)) => (ack, start, count as usize),
+            _ => return,
+        };
+        let keys_end = 13 + count * 2;
+        if data.len() != keys_end + 12 {
+            return;
+        }
+        self.peer_ack = self.peer_ack.max(ack);
+        for (i, bytes) in data[13..keys_end].chunks_exact(2).enumerate() {
+            // only the next frame we're missing, packets can arrive out of order
+            if start + i != self.remote.len() {
+                continue;
+            }
+            let keys = u16::from_le_bytes([bytes[0], bytes[1]]);
+            let frame = self.remote.len();
+            self.remote.push(keys);
+            if self.predicted.get(frame).is_some_and(|&guess| guess != keys) {
+                self.rollback = Some(self.rollback.map_or(frame, |from| from.min(frame)));
+            }
+        }
+        let hash_frame = u32_at(keys_end).unwrap();
+        if hash_frame > 0 {
+            let hash = u64::from_le_bytes(data[keys_end + 4..keys_end + 12].try_into().unwrap());
+            self.peer_hashes.insert(hash_frame, hash);
+        }
+    }
+
+    fn send(&mut self) {
+        let start = self.peer_ack.min(self.local.len());
+        let end = self.local.len().min(start + MAX_INPUTS);
+        let mut data = packet(INPUT);
+        data.extend_from_slice(&(self.remote.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(start as u32).to_le_bytes());
+        data.push((end - start) as u8);
+        for keys in &self.local[start..end] {
+            data.extend_from_slice(&keys.to_le_bytes());
+        }
+        // the latest hash that can't change anymore, from a frame run with real input
+        let (frame, hash) = self.hashes
+            .range(..=self.confirmed())
+            .next_back()
+            .map_or((0, 0), |(&frame, &hash)| (frame, hash));
+        data.extend_from_slice(&(frame as u32).to_le_bytes());
+        data.extend_from_slice(&hash.to_le_bytes());
+        let _ = self.socket.send_to(&data, self.peer);
+    }
+    // frames up to here ran with both players' real keys
+    fn confirmed(&self) -> usize {
+        self.remote.len().min(self.frame)
+    }
+
+    fn compare_hashes(&mut self) {
+        let confirmed = self.confirmed();
+        let mut compared = None;
+        for (&frame, &theirs) in self.peer_hashes.range(..=confirmed) {
+            if let Some(&ours) = self.hashes.get(&frame) {
+                if ours != theirs && !self.desynced {
+                    println!("Netplay desync at frame {}: our state {:016x}, theirs {:016x}", frame, ours, theirs);
+                    self.desynced = true;
+                }
+                compared = Some(frame);
+            }
+        }
+        if let Some(last) = compared {
+            self.hashes.retain(|&frame, _| frame > last);
+            self.peer_hashes.retain(|&frame, _| frame > last);
+        }
+    }
+}
+
+fn packet(kind: u8) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.push(kind);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const PONG: &[u8] = include_bytes!("../../c8games/PONG");
+    // PONG's paddles, 1/4 for player 1 and C/D for player 2
+    const OWNED: [u16; 2] = [1 << 0x1 | 1 << 0x4, 1 << 0xC | 1 << 0xD];
+
+    // both players on this machine, on port and port + 1
+    fn connect_pair(port: u16, delay: usize) -> [(Netplay, Emu); 2] {
+        let side = |player: usize| {
+            thread::spawn(move || {
+                let mut emu = Emu::new();
+                // only player 1's seed should count
+                emu.set_seed(player as u64 + 1);
+                emu.load(PONG).unwrap();
+                let ours = format!("127.0.0.1:{}", port + player as u16);
+                let theirs = format!("127.0.0.1:{}", port + 1 - player as u16);
+                let netplay = Netplay::connect(&ours, &theirs, player, delay, OWNED, 10, &mut emu, PONG);
+                (netplay.unwrap(), emu)
+            })
+        };
+        let (one, two) = (side(0), side(1));
+        [one.join().unwrap(), two.join().unwrap()]
+    }
+
+    // each player moves their paddle up and down, changing every 37 frames
+    fn keys(player: usize, frame: usize) -> u16 {
+        let up = (frame / 37).is_multiple_of(2);
+        match (player, up) {
+            (0, true) => 1 << 0x1,
+            (0, false) => 1 << 0x4,
+            (_, true) => 1 << 0xC,
+            (_, false) => 1 << 0xD,
+        }
+    }
+
+    #[test]
+    fn both_sides_play_the_same_game() {
+        // no input delay, so player 1 always has to guess player 2's keys and every change
+        // of direction means a rollback
+        let mut sides = connect_pair(47310, 0);
+        while sides.iter().any(|(netplay, _)| netplay.frame < 300) {
+            for (player, (netplay, emu)) in sides.iter_mut().enumerate() {
+                if netplay.frame < 300 {
+                    netplay.advance(emu, keys(player, netplay.frame));
+                }
+            }
+        }
+        // once each side has the other's keys for every frame, the guesses are all fixed up
+        for _ in 0..100 {
+            for (netplay, emu) in sides.iter_mut() {
+                netplay.catch_up(emu);
+                netplay.send();
+            }
+            if sides.iter().all(|(netplay, _)| netplay.confirmed() == 300) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let [(one, emu1), (two, emu2)] = &sides;
+        assert_eq!((one.confirmed(), two.confirmed()), (300, 300));
+        assert_eq!(emu1.seed(), emu2.seed());
+        assert_eq!(emu1.state_hash(), emu2.state_hash());
+        assert!(!one.desynced && !two.desynced);
+    }
+
+    #[test]
+    fn stops_when_too_far_ahead_of_the_other_player() {
+        let [(mut one, mut emu), _two] = connect_pair(47320, DEFAULT_INPUT_DELAY);
+        for _ in 0..MAX_ROLLBACK * 3 {
+            one.advance(&mut emu, 0);
+        }
+        assert_eq!(one.frame, MAX_ROLLBACK);
+        assert_eq!(one.states.len(), MAX_ROLLBACK);
+    }
+}
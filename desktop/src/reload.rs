@@ -0,0 +1,95 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use chip8_core::{rom_hash, Emu};
+
+// how often the game's file is looked at
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// how long the file has to stay the same before it's read, so a build that's still
+// writing it doesn't get loaded half done
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+// watches the game's file and loads it again whenever it changes, for writing games
+// there's no file watching in std, so it compares the size and modification time a few
+// times a second, which is cheap next to drawing a frame
+pub struct RomWatcher {
+    path: PathBuf,
+    // keep the registers and the memory outside the program across reloads, see Emu::reload
+    keep_state: bool,
+    // size and modification time when last looked at, None while the file is missing
+    seen: Option<(u64, SystemTime)>,
+    // when they last changed, None once that change has been dealt with
+    changed_at: Option<Instant>,
+    last_poll: Instant,
+}
+
+impl RomWatcher {
+    pub fn new(path: &str, keep_state: bool) -> Self {
+        let path = PathBuf::from(path);
+        RomWatcher {
+            seen: stamp(&path),
+            path,
+            keep_state,
+            changed_at: None,
+            last_poll: Instant::now(),
+        }
+    }
+    // call once a frame, reloads the game once the file has changed and settled
+    pub fn poll(&mut self, emu: &mut Emu) {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return;
+        }
+        self.last_poll = Instant::now();
+        let stamp = stamp(&self.path);
+        if stamp != self.seen {
+            self.seen = stamp;
+            self.changed_at = Some(Instant::now());
+            return;
+        }
+        if self.changed_at.is_some_and(|when| when.elapsed() >= SETTLE_TIME) {
+            self.changed_at = None;
+            // saving the same bytes again (or touching the file) isn't a new build
+            if let Some(data) = self.read().filter(|data| rom_hash(data) != emu.rom_hash()) {
+                self.load(emu, &data);
+            }
+        }
+    }
+    // the reload hotkey, starts the game over from the file even if it hasn't changed
+    pub fn reload(&mut self, emu: &mut Emu) {
+        self.seen = stamp(&self.path);
+        self.changed_at = None;
+        if let Some(data) = self.read() {
+            self.load(emu, &data);
+        }
+    }
+    // the file's contents, None (and a message) when it can't be read whole
+    fn read(&mut self) -> Option<Vec<u8>> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Unable to reload {}: {}", self.path.display(), e);
+                return None;
+            }
+        };
+        // a build still writing is caught by the size moving under us, try again once it settles
+        if stamp(&self.path).is_none_or(|(len, _)| len != data.len() as u64) {
+            self.seen = stamp(&self.path);
+            self.changed_at = Some(Instant::now());
+            return None;
+        }
+        Some(data)
+    }
+
+    fn load(&self, emu: &mut Emu, data: &[u8]) {
+        if let Err(e) = emu.reload(data, self.keep_state) {
+            println!("Unable to reload {}: {}, keeping the game that's running", self.path.display(), e);
+            return;
+        }
+        println!("Reloaded {} ({} bytes{})", self.path.display(), data.len(), if self.keep_state { ", state kept" } else { "" });
+    }
+}
+
+fn stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.len(), meta.modified().ok()?))
+}
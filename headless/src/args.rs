@@ -0,0 +1,85 @@
+use chip8_core::StreamFormat;
+
+pub const USAGE: &str = "\
+Usage: cargo run path/to/game [options]
+       cargo run [path/to/game] --serve ADDR | --stdio
+Options:
+    --frames N       how many 60Hz frames to run (default: 600, ten seconds, or the movie's length)
+    --movie FILE     play back an input movie, failing if it desyncs
+    --gif FILE       record the run as an animated GIF
+    --video FILE     stream every frame to FILE, - for stdout
+    --format F       video format, y4m or raw RGBA (default: y4m)
+    --scale N        recording size as a multiple of 64x32 (default: 4)
+    --palette NAME   colors to record with (default: classic)
+    --serve ADDR     take JSON-RPC commands over TCP, e.g. 127.0.0.1:9000
+    --stdio          take JSON-RPC commands on stdin, one per line";
+
+// default recording size as a multiple of the chip-8 display
+pub const DEFAULT_SCALE: usize = 4;
+// ten seconds of play
+pub const DEFAULT_FRAMES: usize = 600;
+
+// command line options
+pub struct Args {
+    // optional when serving, the client can load one
+    pub rom: Option<String>,
+    pub frames: Option<usize>,
+    pub movie: Option<String>,
+    pub gif: Option<String>,
+    pub video: Option<String>,
+    pub format: StreamFormat,
+    pub scale: usize,
+    pub palette: String,
+    pub serve: Option<String>,
+    pub stdio: bool,
+}
+
+// reads the options that follow the program name
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        rom: None,
+        frames: None,
+        movie: None,
+        gif: None,
+        video: None,
+        format: StreamFormat::Y4m,
+        scale: DEFAULT_SCALE,
+        palette: "classic".to_string(),
+        serve: None,
+        stdio: false,
+    };
+    while let Some(arg) = args.next() {
+        // options that take a value
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--frames" => {
+                let text = value()?;
+                parsed.frames = Some(text.parse().map_err(|_| format!("Invalid frame count: {}", text))?);
+            },
+            "--movie" => parsed.movie = Some(value()?),
+            "--gif" => parsed.gif = Some(value()?),
+            "--video" => parsed.video = Some(value()?),
+            "--format" => {
+                let text = value()?;
+                parsed.format = StreamFormat::named(&text).ok_or_else(|| format!("Unknown format: {}", text))?;
+            },
+            "--scale" => {
+                let text = value()?;
+                parsed.scale = match text.parse() {
+                    Ok(scale) if scale > 0 => scale,
+                    _ => return Err(format!("Invalid scale: {}", text)),
+                };
+            },
+            "--palette" => parsed.palette = value()?,
+            "--serve" => parsed.serve = Some(value()?),
+            "--stdio" => parsed.stdio = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if parsed.rom.is_none() => parsed.rom = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    if parsed.rom.is_none() && parsed.serve.is_none() && !parsed.stdio {
+        return Err("No game given".to_string());
+    }
+    Ok(parsed)
+}
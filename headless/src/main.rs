@@ -0,0 +1,112 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use chip8_core::*;
+
+mod args;
+mod server;
+
+// same as the desktop frontend, unless the game's profile asks for something else
+const TICK_PER_FRAME: u32 = 5;
+
+// runs a game without a window, for recording clips and checking behaviour from scripts
+// everything it says goes to stderr, so stdout stays free for a video stream
+fn main() {
+    let args = match args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, args::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let result = if args.serve.is_some() || args.stdio {
+        server::run(&args)
+    } else {
+        run(&args)
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+// loads a game and sets the emulator up for it, returns the game's profile and speed
+// same profile lookup as the desktop, so games run with the right quirks and speed
+// fails if the game is empty or doesn't fit in memory, with the emulator already reset
+pub fn setup(chip8: &mut Emu, rom: &[u8]) -> Result<(RomProfile, u32), String> {
+    chip8.reset();
+    chip8.load(rom)?;
+    let mut profile = RomDb::bundled().lookup(chip8.rom_hash()).cloned().unwrap_or_default();
+    if profile.quirks().is_none() {
+        profile.fill_from(&analyze(chip8.program()));
+    }
+    chip8.set_quirks(profile.quirks().unwrap_or_default());
+    let ticks_per_frame = profile.ticks_per_frame.unwrap_or(TICK_PER_FRAME);
+    Ok((profile, ticks_per_frame))
+}
+
+fn run(args: &args::Args) -> Result<(), String> {
+    let path = args.rom.as_deref().ok_or("No game given")?;
+    let rom = fs::read(path).map_err(|e| format!("Unable to open {}: {}", path, e))?;
+    let mut chip8 = Emu::new();
+    // refuses a game that doesn't fit before anything runs
+    let (_, mut ticks_per_frame) = setup(&mut chip8, &rom).map_err(|e| format!("Unable to load {}: {}", path, e))?;
+    // a movie brings its own settings
+    let movie = match &args.movie {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("Unable to open {}: {}", path, e))?;
+            let movie = Movie::from_toml(&text)?;
+            movie.start(&mut chip8, &rom)?;
+            ticks_per_frame = movie.ticks_per_frame();
+            Some(movie)
+        },
+        None => None,
+    };
+    let frames = args.frames.unwrap_or(movie.as_ref().map_or(args::DEFAULT_FRAMES, Movie::len));
+    let palette = Palette::named(&args.palette).ok_or_else(|| format!("Unknown palette: {}", args.palette))?;
+
+    let mut gif = args.gif.as_ref().map(|_| GifRecorder::new(args.scale, palette));
+    let mut video = match args.video.as_deref() {
+        None => None,
+        Some("-") => Some(FrameStream::new(Box::new(io::stdout().lock()) as Box<dyn Write>, args.format, args.scale, palette)),
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path, e))?;
+            Some(FrameStream::new(Box::new(BufWriter::new(file)) as Box<dyn Write>, args.format, args.scale, palette))
+        },
+    };
+
+    for frame in 0..frames {
+        // once the movie is over nothing is pressed
+        if let Some(movie) = &movie {
+            chip8.set_key_mask(movie.keys(frame).unwrap_or(0));
+        }
+        // a crashed game would only record garbage from here on
+        for _ in 0..ticks_per_frame {
+            chip8.try_tick().map_err(|e| format!("The game crashed on frame {}: {}", frame, e))?;
+        }
+        chip8.tick_timers();
+        if let Some(movie) = &movie {
+            movie.check(frame + 1, &chip8).map_err(|e| e.to_string())?;
+        }
+        if let Some(gif) = &mut gif {
+            gif.capture(chip8.get_display());
+        }
+        if let Some(video) = &mut video {
+            video.write_frame(chip8.get_display()).map_err(|e| format!("Unable to write video: {}", e))?;
+        }
+    }
+
+    if let Some(movie) = &movie {
+        eprintln!("Movie played back in sync ({} of {} frames)", frames.min(movie.len()), movie.len());
+    }
+    if let (Some(gif), Some(path)) = (gif, &args.gif) {
+        fs::write(path, gif.finish()).map_err(|e| format!("Unable to save {}: {}", path, e))?;
+        eprintln!("Saved {} ({} frames)", path, gif.frame_count());
+    }
+    if let (Some(video), Some(path)) = (video, &args.video) {
+        let frames = video.frame_count();
+        video.finish().map_err(|e| format!("Unable to write video: {}", e))?;
+        eprintln!("Wrote {} frames of {} to {}", frames, args.format.name(), path);
+    }
+    Ok(())
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+use chip8_core::*;
+use crate::args::Args;
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// anything that went wrong running a valid request, like a missing file
+const SERVER_ERROR: i64 = -32000;
+
+// an error to send back, a code and a message
+type RpcError = (i64, String);
+
+// drives one emulator from JSON-RPC requests, one JSON object per line
+// over TCP clients are served one after another and all share the same emulator,
+// so a script can reconnect and carry on where it left off
+pub fn run(args: &Args) -> Result<(), String> {
+    let mut server = Server::new();
+    if let Some(path) = &args.rom {
+        server.load_rom(&json!({ "path": path })).map_err(|(_, e)| e)?;
+    }
+    if let Some(addr) = &args.serve {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("Unable to listen on {}: {}", addr, e))?;
+        eprintln!("Listening on {}", addr);
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Connection failed: {}", e);
+                    continue;
+                }
+            };
+            let reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+            // a client going away is normal, wait for the next one
+            if let Err(e) = server.serve(reader, stream) {
+                eprintln!("Connection closed: {}", e);
+            }
+        }
+        Ok(())
+    } else {
+        server.serve(io::stdin().lock(), io::stdout().lock()).map_err(|e| e.to_string())
+    }
+}
+
+struct Server {
+    emu: Emu,
+    rom: Vec<u8>,
+    ticks_per_frame: u32,
+    // save states by name, kept in memory for as long as the server runs
+    states: HashMap<String, SaveState>,
+}
+
+impl Server {
+    fn new() -> Self {
+        Self { emu: Emu::new(), rom: Vec::new(), ticks_per_frame: 0, states: HashMap::new() }
+    }
+    // answers requests until the other end closes
+    fn serve(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle_line(&line) {
+                writeln!(output, "{}", response)?;
+                output.flush()?;
+            }
+        }
+        Ok(())
+    }
+    // a request or a batch of them, None when there's nothing to answer (only notifications)
+    fn handle_line(&mut self, line: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return Some(error(Value::Null, (PARSE_ERROR, e.to_string()))),
+        };
+        match request {
+            Value::Array(batch) if batch.is_empty() => Some(error(Value::Null, (INVALID_REQUEST, "Empty batch".to_string()))),
+            Value::Array(batch) => {
+                let responses: Vec<Value> = batch.into_iter().filter_map(|request| self.handle(request)).collect();
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            },
+            request => self.handle(request),
+        }
+    }
+
+    fn handle(&mut self, request: Value) -> Option<Value> {
+        let id = request.get("id").cloned();
+        let method = match request.get("method").and_then(Value::as_str) {
+            Some(method) if request.get("jsonrpc") == Some(&json!("2.0")) => method,
+            _ => return Some(error(id.unwrap_or(Value::Null), (INVALID_REQUEST, "Not a JSON-RPC 2.0 request".to_string()))),
+        };
+        let params = request.get("params").cloned().unwrap_or(json!({}));
+        let result = self.call(method, &params);
+        // requests without an id are notifications, they get no answer
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => error(id, e),
+        })
+    }
+
+    fn call(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "load_rom" => self.load_rom(params),
+            "reset" => {
+                if self.rom.is_empty() {
+                    return Err((SERVER_ERROR, "No game loaded".to_string()));
+                }
+                let rom = self.rom.clone();
+                crate::setup(&mut self.emu, &rom).map_err(|e| (SERVER_ERROR, e))?;
+                if let Some(seed) = opt_u64(params, "seed")? {
+                    self.emu.set_seed(seed);
+                }
+                Ok(Value::Null)
+            },
+            // count instructions
+            "step" => {
+                for _ in 0..opt_u64(params, "count")?.unwrap_or(1) {
+                    self.emu.try_tick().map_err(fault)?;
+                }
+                Ok(json!({ "pc": self.emu.cpu().pc }))
+            },
+            // count 60Hz frames at the game's speed
+            "run_frames" => {
+                for _ in 0..opt_u64(params, "count")?.unwrap_or(1) {
+                    for _ in 0..self.ticks_per_frame {
+                        self.emu.try_tick().map_err(fault)?;
+                    }
+                    self.emu.tick_timers();
+                }
+                Ok(json!({ "pc": self.emu.cpu().pc }))
+            },
+            "press" | "release" => {
+                let key = req_u64(params, "key")? as usize;
+                if key >= NUM_KEYS {
+                    return Err((INVALID_PARAMS, format!("There is no key {:#x}", key)));
+                }
+                self.emu.keypress(key, method == "press");
+                Ok(Value::Null)
+            },
+            "read_memory" => {
+                let addr = req_u64(params, "address")?;
+                let len = req_u64(params, "length")? as usize;
+                let data = u16::try_from(addr).ok()
+                    .and_then(|addr| self.emu.read_ram(addr, len))
+                    .ok_or((INVALID_PARAMS, format!("{} bytes at {:#x} are outside memory", len, addr)))?;
+                Ok(json!({ "data": BASE64.encode(data) }))
+            },
+            "write_memory" => {
+                let addr = u16::try_from(req_u64(params, "address")?)
+                    .map_err(|_| (INVALID_PARAMS, "address is outside memory".to_string()))?;
+                let data = params.get("data").and_then(Value::as_str)
+                    .and_then(|data| BASE64.decode(data).ok())
+                    .ok_or((INVALID_PARAMS, "data should be base64".to_string()))?;
+                self.emu.debug().write_ram(addr, &data).map_err(|e| (INVALID_PARAMS, e))?;
+                Ok(Value::Null)
+            },
+            "get_registers" => Ok(registers(&self.emu.cpu())),
+            "set_registers" => self.set_registers(params),
+            "get_display" => {
+                let screen = self.emu.get_display();
+                match params.get("format").and_then(Value::as_str).unwrap_or("base64") {
+                    // 1 bit per pixel, 8 pixels to a byte with the leftmost in the high bit, row by row
+                    "base64" => {
+                        let bytes: Vec<u8> = screen
+                            .chunks(8)
+                            .map(|px| px.iter().fold(0, |byte, &on| byte << 1 | on as u8))
+                            .collect();
+                        Ok(json!({ "width": SCREEN_WIDTH, "height": SCREEN_HEIGHT, "data": BASE64.encode(bytes) }))
+                    },
+                    // a string of 0s and 1s per row
+                    "rows" => {
+                        let rows: Vec<String> = screen
+                            .chunks(SCREEN_WIDTH)
+                            .map(|row| row.iter().map(|&on| if on { '1' } else { '0' }).collect())
+                            .collect();
+                        Ok(json!(rows))
+                    },
+                    format => Err((INVALID_PARAMS, format!("Unknown display format: {}", format))),
+                }
+            },
+            "save_state" => {
+                let name = opt_str(params, "name")?.unwrap_or("default");
+                self.states.insert(name.to_string(), self.emu.save_state());
+                Ok(Value::Null)
+            },
+            "load_state" => {
+                let name = opt_str(params, "name")?.unwrap_or("default");
+                let state = self.states.get(name).ok_or((SERVER_ERROR, format!("No state called {}", name)))?;
+                self.emu.load_state(state);
+                Ok(Value::Null)
+            },
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+        }
+    }
+    // from a file on this machine ("path") or sent over ("data", base64)
+    fn load_rom(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let rom = if let Some(path) = opt_str(params, "path")? {
+            fs::read(path).map_err(|e| (SERVER_ERROR, format!("Unable to open {}: {}", path, e)))?
+        } else if let Some(data) = opt_str(params, "data")? {
+            BASE64.decode(data).map_err(|_| (INVALID_PARAMS, "data should be base64".to_string()))?
+        } else {
+            return Err((INVALID_PARAMS, "load_rom needs a path or data".to_string()));
+        };
+        // set up a copy, so a game that doesn't load leaves the current one running
+        let mut emu = self.emu.clone();
+        let (profile, ticks_per_frame) = crate::setup(&mut emu, &rom).map_err(|e| (INVALID_PARAMS, e))?;
+        self.emu = emu;
+        self.rom = rom;
+        self.ticks_per_frame = ticks_per_frame;
+        self.states.clear();
+        Ok(json!({
+            "hash": format!("{:016x}", self.emu.rom_hash()),
+            "title": profile.title,
+            "platform": profile.platform.map(|platform| platform.name()),
+            "ticks_per_frame": ticks_per_frame,
+        }))
+    }
+    // any of pc, i, v (all 16), stack, dt and st, the rest stay as they are
+    fn set_registers(&mut self, params: &Value) -> Result<Value, RpcError> {
+        let invalid = |e: String| (INVALID_PARAMS, e);
+        let v = match params.get("v") {
+            Some(v) => Some(
+                v.as_array()
+                    .filter(|v| v.len() == 16)
+                    .and_then(|v| v.iter().map(|val| val.as_u64().and_then(|val| u8::try_from(val).ok())).collect::<Option<Vec<u8>>>())
+                    .ok_or_else(|| invalid("v should be 16 numbers from 0 to 255".to_string()))?,
+            ),
+            None => None,
+        };
+        let stack = match params.get("stack") {
+            Some(stack) => Some(
+                stack.as_array()
+                    .and_then(|stack| stack.iter().map(|val| val.as_u64().and_then(|val| u16::try_from(val).ok())).collect::<Option<Vec<u16>>>())
+                    .ok_or_else(|| invalid("stack should be a list of addresses".to_string()))?,
+            ),
+            None => None,
+        };
+        let (pc, i) = (opt_u16(params, "pc")?, opt_u16(params, "i")?);
+        let (dt, st) = (opt_u8(params, "dt")?, opt_u8(params, "st")?);
+        // all or nothing, a bad value leaves everything as it was
+        let before = self.emu.save_state();
+        let result = self.apply_registers(pc, i, v, stack, dt, st);
+        if result.is_err() {
+            self.emu.load_state(&before);
+        }
+        result.map_err(|e| (INVALID_PARAMS, e))?;
+        Ok(registers(&self.emu.cpu()))
+    }
+
+    fn apply_registers(
+        &mut self,
+        pc: Option<u16>,
+        i: Option<u16>,
+        v: Option<Vec<u8>>,
+        stack: Option<Vec<u16>>,
+        dt: Option<u8>,
+        st: Option<u8>,
+    ) -> Result<(), String> {
+        let mut debug = self.emu.debug();
+        if let Some(pc) = pc {
+            debug.set_pc(pc)?;
+        }
+        if let Some(i) = i {
+            debug.set_i(i)?;
+        }
+        for (reg, &value) in v.iter().flatten().enumerate() {
+            debug.set_v(reg, value)?;
+        }
+        if let Some(stack) = stack {
+            debug.set_stack(&stack)?;
+        }
+        if let Some(dt) = dt {
+            debug.set_dt(dt);
+        }
+        if let Some(st) = st {
+            debug.set_st(st);
+        }
+        Ok(())
+    }
+}
+
+fn registers(cpu: &CpuState) -> Value {
+    json!({
+        "pc": cpu.pc,
+        "i": cpu.i,
+        "v": cpu.v,
+        "stack": &cpu.stack[..cpu.sp as usize],
+        "dt": cpu.dt,
+        "st": cpu.st,
+        "keys": cpu.keys.iter().enumerate().fold(0u16, |mask, (i, &held)| mask | (held as u16) << i),
+    })
+}
+
+// a game that crashed, sent back to the client instead of taking the server down
+fn fault(fault: Fault) -> RpcError {
+    (SERVER_ERROR, fault.to_string())
+}
+
+fn error(id: Value, (code, message): RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+// reading parameters, by name from an object
+fn opt_u64(params: &Value, name: &str) -> Result<Option<u64>, RpcError> {
+    match params.get(name) {
+        None => Ok(None),
+        Some(value) => value.as_u64().map(Some).ok_or((INVALID_PARAMS, format!("{} should be a whole number", name))),
+    }
+}
+
+fn req_u64(params: &Value, name: &str) -> Result<u64, RpcError> {
+    opt_u64(params, name)?.ok_or((INVALID_PARAMS, format!("Missing {}", name)))
+}
+
+fn opt_u16(params: &Value, name: &str) -> Result<Option<u16>, RpcError> {
+    opt_u64(params, name)?
+        .map(|value| u16::try_from(value).map_err(|_| (INVALID_PARAMS, format!("{} is too big", name))))
+        .transpose()
+}
+
+fn opt_u8(params: &Value, name: &str) -> Result<Option<u8>, RpcError> {
+    opt_u64(params, name)?
+        .map(|value| u8::try_from(value).map_err(|_| (INVALID_PARAMS, format!("{} is too big", name))))
+        .transpose()
+}
+
+fn opt_str<'a>(params: &'a Value, name: &str) -> Result<Option<&'a str>, RpcError> {
+    match params.get(name) {
+        None => Ok(None),
+        Some(value) => value.as_str().map(Some).ok_or((INVALID_PARAMS, format!("{} should be a string", name))),
+    }
+}
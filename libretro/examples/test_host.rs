@@ -0,0 +1,250 @@
+// a bare-bones libretro frontend that loads the built core and checks it behaves
+// $ cargo build && cargo run --example test_host -- target/debug/libchip8_libretro.so ../c8games/BRIX
+use chip8_libretro::ffi::*;
+use libloading::{Library, Symbol};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{c_uint, c_void, CStr, CString};
+use std::fs;
+use std::process;
+use std::ptr;
+use std::sync::Mutex;
+
+// what the core has sent us, and the buttons we hold, shared with the callbacks
+struct Host {
+    options: HashMap<String, String>, // declared options and their descriptions
+    chosen: HashMap<String, CString>, // values we answer GET_VARIABLE with
+    pixel_format: Option<c_uint>,
+    descriptors: Vec<String>,
+    messages: Vec<String>,
+    frame: Vec<u32>,
+    frames: usize,
+    samples: usize,
+    loud_samples: usize,
+    held: Vec<(c_uint, c_uint)>, // (port, button)
+}
+
+static HOST: Mutex<Option<Host>> = Mutex::new(None);
+
+fn with_host<T>(f: impl FnOnce(&mut Host) -> T) -> T {
+    f(HOST.lock().unwrap().as_mut().unwrap())
+}
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    with_host(|host| match cmd {
+        ENVIRONMENT_SET_VARIABLES => {
+            let mut variable = data as *const Variable;
+            while !(*variable).key.is_null() {
+                let key = CStr::from_ptr((*variable).key).to_string_lossy().into_owned();
+                let value = CStr::from_ptr((*variable).value).to_string_lossy().into_owned();
+                host.options.insert(key, value);
+                variable = variable.add(1);
+            }
+            true
+        },
+        ENVIRONMENT_GET_VARIABLE => {
+            let variable = &mut *(data as *mut Variable);
+            let key = CStr::from_ptr(variable.key).to_string_lossy();
+            match host.chosen.get(key.as_ref()) {
+                Some(value) => {
+                    variable.value = value.as_ptr();
+                    true
+                },
+                None => false,
+            }
+        },
+        ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            *(data as *mut bool) = false;
+            true
+        },
+        ENVIRONMENT_SET_PIXEL_FORMAT => {
+            host.pixel_format = Some(*(data as *const c_uint));
+            true
+        },
+        ENVIRONMENT_SET_INPUT_DESCRIPTORS => {
+            let mut descriptor = data as *const InputDescriptor;
+            while !(*descriptor).description.is_null() {
+                let d = &*descriptor;
+                let name = CStr::from_ptr(d.description).to_string_lossy();
+                host.descriptors.push(format!("port {} button {}: {}", d.port, d.id, name));
+                descriptor = descriptor.add(1);
+            }
+            true
+        },
+        ENVIRONMENT_SET_MESSAGE => {
+            let message = &*(data as *const Message);
+            host.messages.push(CStr::from_ptr(message.msg).to_string_lossy().into_owned());
+            true
+        },
+        _ => false,
+    })
+}
+
+unsafe extern "C" fn video(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    assert_eq!((width, height, pitch), (64, 32, 256));
+    let pixels = std::slice::from_raw_parts(data as *const u32, (width * height) as usize);
+    with_host(|host| {
+        host.frame = pixels.to_vec();
+        host.frames += 1;
+    });
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+unsafe extern "C" fn audio_batch(data: *const i16, frames: usize) -> usize {
+    let samples = std::slice::from_raw_parts(data, frames * 2);
+    with_host(|host| {
+        host.samples += frames;
+        host.loud_samples += samples.chunks(2).filter(|s| s[0] != 0).count();
+    });
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    assert_eq!(device, DEVICE_JOYPAD);
+    with_host(|host| host.held.contains(&(port, id)) as i16)
+}
+
+fn check(ok: bool, what: &str) {
+    if !ok {
+        eprintln!("FAILED: {}", what);
+        process::exit(1);
+    }
+    println!("ok: {}", what);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("Usage: test_host path/to/chip8_libretro.so path/to/game");
+        process::exit(2);
+    }
+    let rom = fs::read(&args[2]).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", args[2], e);
+        process::exit(2);
+    });
+    *HOST.lock().unwrap() = Some(Host {
+        options: HashMap::new(),
+        chosen: HashMap::new(),
+        pixel_format: None,
+        descriptors: Vec::new(),
+        messages: Vec::new(),
+        frame: Vec::new(),
+        frames: 0,
+        samples: 0,
+        loud_samples: 0,
+        held: Vec::new(),
+    });
+
+    unsafe {
+        let lib = Library::new(&args[1]).unwrap_or_else(|e| {
+            eprintln!("Unable to open {}: {}", args[1], e);
+            process::exit(2);
+        });
+        macro_rules! sym {
+            ($name:ident: $ty:ty) => {
+                let $name: Symbol<$ty> = lib.get(concat!(stringify!($name), "\0").as_bytes()).unwrap();
+            };
+        }
+        sym!(retro_api_version: unsafe extern "C" fn() -> c_uint);
+        sym!(retro_set_environment: unsafe extern "C" fn(EnvironmentFn));
+        sym!(retro_set_video_refresh: unsafe extern "C" fn(VideoRefreshFn));
+        sym!(retro_set_audio_sample: unsafe extern "C" fn(AudioSampleFn));
+        sym!(retro_set_audio_sample_batch: unsafe extern "C" fn(AudioSampleBatchFn));
+        sym!(retro_set_input_poll: unsafe extern "C" fn(InputPollFn));
+        sym!(retro_set_input_state: unsafe extern "C" fn(InputStateFn));
+        sym!(retro_init: unsafe extern "C" fn());
+        sym!(retro_deinit: unsafe extern "C" fn());
+        sym!(retro_get_system_info: unsafe extern "C" fn(*mut SystemInfo));
+        sym!(retro_get_system_av_info: unsafe extern "C" fn(*mut SystemAvInfo));
+        sym!(retro_load_game: unsafe extern "C" fn(*const GameInfo) -> bool);
+        sym!(retro_unload_game: unsafe extern "C" fn());
+        sym!(retro_run: unsafe extern "C" fn());
+        sym!(retro_reset: unsafe extern "C" fn());
+        sym!(retro_serialize_size: unsafe extern "C" fn() -> usize);
+        sym!(retro_serialize: unsafe extern "C" fn(*mut c_void, usize) -> bool);
+        sym!(retro_unserialize: unsafe extern "C" fn(*const c_void, usize) -> bool);
+
+        check(retro_api_version() == API_VERSION, "api version");
+        retro_set_environment(environment);
+        retro_set_video_refresh(video);
+        retro_set_audio_sample(audio_sample);
+        retro_set_audio_sample_batch(audio_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+
+        let mut info = std::mem::zeroed::<SystemInfo>();
+        retro_get_system_info(&mut info);
+        println!("core: {} {}", CStr::from_ptr(info.library_name).to_string_lossy(), CStr::from_ptr(info.library_version).to_string_lossy());
+        with_host(|host| {
+            let mut options: Vec<_> = host.options.iter().collect();
+            options.sort();
+            for (key, value) in options {
+                println!("option {} = {}", key, value);
+            }
+        });
+        check(with_host(|host| ["chip8_speed", "chip8_quirks", "chip8_palette"].iter().all(|key| host.options.contains_key(*key))), "options declared");
+
+        let game = GameInfo { path: ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: ptr::null() };
+        check(retro_load_game(&game), "game loads");
+        check(with_host(|host| host.pixel_format) == Some(PIXEL_FORMAT_XRGB8888), "XRGB8888 pixels");
+        with_host(|host| println!("inputs: {}", host.descriptors.join(", ")));
+        check(with_host(|host| !host.descriptors.is_empty()), "input descriptors");
+        let mut av = std::mem::zeroed::<SystemAvInfo>();
+        retro_get_system_av_info(&mut av);
+        check(av.geometry.base_width == 64 && av.timing.fps == 60.0, "64x32 at 60fps");
+
+        for _ in 0..60 {
+            retro_run();
+        }
+        let lit = |host: &mut Host| host.frame.iter().filter(|&&px| px != 0).count();
+        check(with_host(|host| host.frames == 60 && lit(host) > 0), "a second of video with something drawn");
+        check(with_host(|host| host.samples == 60 * av.timing.sample_rate as usize / 60), "a second of audio");
+
+        // save, play on with right held, go back and play the same frames again
+        let size = retro_serialize_size();
+        let mut state = vec![0u8; size];
+        check(retro_serialize(state.as_mut_ptr() as *mut c_void, size), "serialize");
+        with_host(|host| host.held = vec![(0, JOYPAD_RIGHT)]);
+        for _ in 0..600 {
+            retro_run();
+        }
+        let after = with_host(|host| host.frame.clone());
+        check(with_host(|host| host.loud_samples > 0), "the beeper sounds");
+        check(retro_unserialize(state.as_ptr() as *const c_void, size), "unserialize");
+        for _ in 0..600 {
+            retro_run();
+        }
+        check(with_host(|host| host.frame == after), "a loaded state replays the same frames");
+        check(!retro_unserialize(state.as_ptr() as *const c_void, 10), "a short state is refused");
+
+        // speed and quirks come from the options, the next load picks them up
+        with_host(|host| {
+            host.chosen.insert("chip8_speed".to_string(), CString::new("100").unwrap());
+            host.chosen.insert("chip8_palette".to_string(), CString::new("amber").unwrap());
+        });
+        retro_reset();
+        check(retro_load_game(&game), "game reloads with options");
+        retro_run();
+        check(with_host(|host| host.frame.contains(&0x1a0f00)), "palette option");
+
+        // a game that faults stops with a message instead of taking the frontend down
+        let bad = [0x00u8, 0xEE];
+        let game = GameInfo { path: ptr::null(), data: bad.as_ptr() as *const c_void, size: bad.len(), meta: ptr::null() };
+        check(retro_load_game(&game), "faulty game loads");
+        retro_run();
+        check(with_host(|host| host.messages.iter().any(|m| m.contains("empty stack"))), "fault message");
+        retro_run();
+        check(with_host(|host| host.messages.len() == 1), "stays stopped after a fault");
+        retro_reset();
+        retro_run();
+        check(with_host(|host| host.messages.len() == 2), "a reset starts it again");
+
+        retro_unload_game();
+        retro_deinit();
+    }
+    println!("all libretro checks passed");
+}
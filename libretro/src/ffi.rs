@@ -0,0 +1,102 @@
+// the parts of libretro.h this core uses, written out by hand
+// https://github.com/libretro/RetroArch/blob/master/libretro-common/include/libretro.h
+use std::ffi::{c_char, c_uint, c_void};
+
+pub const API_VERSION: c_uint = 1;
+
+pub const DEVICE_JOYPAD: c_uint = 1;
+// joypad buttons, laid out like a SNES pad (B at the bottom, A on the right)
+pub const JOYPAD_B: c_uint = 0;
+pub const JOYPAD_Y: c_uint = 1;
+pub const JOYPAD_SELECT: c_uint = 2;
+pub const JOYPAD_START: c_uint = 3;
+pub const JOYPAD_UP: c_uint = 4;
+pub const JOYPAD_DOWN: c_uint = 5;
+pub const JOYPAD_LEFT: c_uint = 6;
+pub const JOYPAD_RIGHT: c_uint = 7;
+pub const JOYPAD_A: c_uint = 8;
+pub const JOYPAD_X: c_uint = 9;
+pub const JOYPAD_L: c_uint = 10;
+pub const JOYPAD_R: c_uint = 11;
+pub const JOYPAD_L2: c_uint = 12;
+pub const JOYPAD_R2: c_uint = 13;
+pub const JOYPAD_L3: c_uint = 14;
+pub const JOYPAD_R3: c_uint = 15;
+
+pub const ENVIRONMENT_SET_MESSAGE: c_uint = 6;
+pub const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+pub const ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+pub const ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+pub const ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+pub const ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+
+pub const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+pub const REGION_NTSC: c_uint = 0;
+
+pub type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = unsafe extern "C" fn();
+pub type InputStateFn = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+// a core option, value is "Description; default|other|..." when setting
+// and just the chosen value when getting
+#[repr(C)]
+pub struct Variable {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+#[repr(C)]
+pub struct InputDescriptor {
+    pub port: c_uint,
+    pub device: c_uint,
+    pub index: c_uint,
+    pub id: c_uint,
+    pub description: *const c_char,
+}
+
+#[repr(C)]
+pub struct Message {
+    pub msg: *const c_char,
+    pub frames: c_uint,
+}
@@ -0,0 +1,423 @@
+// the emulator as a libretro core, for RetroArch and other libretro frontends
+// libretro drives a single emulator through global functions, so the core and the frontend's
+// callbacks live in statics
+// every function trusts the frontend to pass what libretro.h says it passes
+#![allow(clippy::missing_safety_doc)]
+
+use chip8_core::*;
+use std::ffi::{c_char, c_uint, c_void, CStr, CString};
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+pub mod ffi;
+use ffi::*;
+
+// ticks per frame for games the database doesn't know, the same as the web frontend
+const TICKS_PER_FRAME: u32 = 10;
+const SAMPLE_RATE: u32 = 44100;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / 60) as usize;
+// chip-8 has one sound, a beep for as long as the sound timer runs, played as a 440Hz square wave
+const TONE: u32 = 440;
+const VOLUME: i16 = 3000;
+
+// core options, the first value listed is the default
+const SPEED_OPTION: &CStr = c"chip8_speed";
+const SPEEDS: &str = "auto|5|7|10|15|20|30|50|100";
+const QUIRKS_OPTION: &CStr = c"chip8_quirks";
+const QUIRK_SETS: &str = "auto|none|chip-8|super-chip|xo-chip";
+const PALETTE_OPTION: &CStr = c"chip8_palette";
+
+// libretro joypad buttons and the SDL-style names the game database binds keys to
+// paired by where they sit on the pad, so libretro's B (bottom) is SDL's a
+const BUTTONS: [(c_uint, &str); 16] = [
+    (JOYPAD_B, "a"), (JOYPAD_A, "b"), (JOYPAD_Y, "x"), (JOYPAD_X, "y"),
+    (JOYPAD_UP, "dpup"), (JOYPAD_DOWN, "dpdown"), (JOYPAD_LEFT, "dpleft"), (JOYPAD_RIGHT, "dpright"),
+    (JOYPAD_SELECT, "back"), (JOYPAD_START, "start"),
+    (JOYPAD_L, "leftshoulder"), (JOYPAD_R, "rightshoulder"),
+    (JOYPAD_L2, "lefttrigger"), (JOYPAD_R2, "righttrigger"),
+    (JOYPAD_L3, "leftstick"), (JOYPAD_R3, "rightstick"),
+];
+const PLAYERS: usize = 2;
+
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video: Option<VideoRefreshFn>,
+    audio_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+// everything about the loaded game
+struct Core {
+    emu: Emu,
+    rom: Vec<u8>,
+    profile: RomProfile,
+    ticks_per_frame: u32,
+    palette: Palette,
+    // controller layout for each player, from the game database
+    pads: [Keymap; PLAYERS],
+    // the display in XRGB8888, handed to the frontend every frame
+    frame: [u32; SCREEN_WIDTH * SCREEN_HEIGHT],
+    audio: [i16; SAMPLES_PER_FRAME * 2],
+    // where the square wave is, kept across frames so the tone doesn't click
+    phase: u32,
+    // set when the game faults, nothing runs until a reset or a state is loaded
+    halted: bool,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video: None,
+    audio_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> MutexGuard<'static, Callbacks> {
+    CALLBACKS.lock().unwrap()
+}
+
+fn core() -> MutexGuard<'static, Option<Core>> {
+    CORE.lock().unwrap()
+}
+
+impl Core {
+    fn new(rom: &[u8]) -> Result<Self, String> {
+        let mut emu = Emu::new();
+        emu.load(rom)?;
+        let profile = RomDb::bundled().lookup(emu.rom_hash()).cloned().unwrap_or_default();
+        let mut core = Core {
+            emu,
+            rom: rom.to_vec(),
+            pads: [profile.gamepad(0), profile.gamepad(1)],
+            profile,
+            ticks_per_frame: TICKS_PER_FRAME,
+            palette: Palette::default(),
+            frame: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            audio: [0; SAMPLES_PER_FRAME * 2],
+            phase: 0,
+            halted: false,
+        };
+        core.apply_options();
+        Ok(core)
+    }
+    // reads the core options, "auto" goes with the game database
+    fn apply_options(&mut self) {
+        self.ticks_per_frame = match option(SPEED_OPTION).and_then(|speed| speed.parse().ok()) {
+            Some(ticks) => ticks,
+            None => self.profile.ticks_per_frame.unwrap_or(TICKS_PER_FRAME),
+        };
+        let quirks = match option(QUIRKS_OPTION).as_deref() {
+            Some("none") => Quirks::default(),
+            Some("chip-8") => Quirks::for_platform(Platform::Chip8),
+            Some("super-chip") => Quirks::for_platform(Platform::SuperChip),
+            Some("xo-chip") => Quirks::for_platform(Platform::XoChip),
+            _ => self.profile.quirks().unwrap_or_default(),
+        };
+        self.emu.set_quirks(quirks);
+        self.palette = option(PALETTE_OPTION).and_then(|name| Palette::named(&name)).unwrap_or_default();
+    }
+
+    fn reset(&mut self) {
+        let quirks = self.emu.quirks();
+        self.emu.reset();
+        self.emu.load(&self.rom).expect("new checked the game fits");
+        self.emu.set_quirks(quirks);
+        self.halted = false;
+    }
+    // one 60Hz frame: input, ticks, timers, then the picture and sound that go with it
+    fn run_frame(&mut self, input_state: Option<InputStateFn>) {
+        if let Some(input_state) = input_state {
+            let mut keys = 0u16;
+            for (port, pad) in self.pads.iter().enumerate() {
+                for (id, name) in BUTTONS {
+                    let held = unsafe { input_state(port as c_uint, DEVICE_JOYPAD, 0, id) } != 0;
+                    if let (true, Some(key)) = (held, pad.lookup(name)) {
+                        keys |= 1 << key;
+                    }
+                }
+            }
+            self.emu.set_key_mask(keys);
+        }
+        if !self.halted {
+            for _ in 0..self.ticks_per_frame {
+                if let Err(fault) = self.emu.try_tick() {
+                    self.halted = true;
+                    show_message(&format!("CHIP-8 stopped: {}", fault));
+                    break;
+                }
+            }
+            self.emu.tick_timers();
+        }
+        for (px, &lit) in self.frame.iter_mut().zip(self.emu.get_display()) {
+            let Rgb(r, g, b) = self.palette.color(lit);
+            *px = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        }
+        let beeping = !self.halted && self.emu.sound_active();
+        for frame in self.audio.chunks_mut(2) {
+            let level = if !beeping {
+                0
+            } else if self.phase < SAMPLE_RATE / 2 {
+                VOLUME
+            } else {
+                -VOLUME
+            };
+            frame.fill(level);
+            self.phase = (self.phase + TONE) % SAMPLE_RATE;
+        }
+    }
+    // tells the frontend what each button does in this game, for its input settings
+    fn describe_input(&self) {
+        let mut names = Vec::new();
+        let mut descriptors = Vec::new();
+        for (port, pad) in self.pads.iter().enumerate() {
+            for (id, name) in BUTTONS {
+                if let Some(key) = pad.lookup(name) {
+                    names.push((port, id, CString::new(format!("Key {:X}", key)).unwrap()));
+                }
+            }
+        }
+        for (port, id, name) in &names {
+            descriptors.push(InputDescriptor {
+                port: *port as c_uint,
+                device: DEVICE_JOYPAD,
+                index: 0,
+                id: *id,
+                description: name.as_ptr(),
+            });
+        }
+        // the list ends with an empty entry
+        descriptors.push(InputDescriptor { port: 0, device: 0, index: 0, id: 0, description: ptr::null() });
+        environment(ENVIRONMENT_SET_INPUT_DESCRIPTORS, descriptors.as_mut_ptr() as *mut c_void);
+    }
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    let environment = callbacks().environment;
+    environment.is_some_and(|environment| unsafe { environment(cmd, data) })
+}
+// the frontend's current value for a core option
+fn option(key: &CStr) -> Option<String> {
+    let mut variable = Variable { key: key.as_ptr(), value: ptr::null() };
+    if !environment(ENVIRONMENT_GET_VARIABLE, &mut variable as *mut Variable as *mut c_void) || variable.value.is_null() {
+        return None;
+    }
+    let value = unsafe { CStr::from_ptr(variable.value) };
+    Some(value.to_string_lossy().into_owned())
+}
+// a note on screen for a few seconds
+fn show_message(text: &str) {
+    let text = CString::new(text).unwrap_or_default();
+    let mut message = Message { msg: text.as_ptr(), frames: 180 };
+    environment(ENVIRONMENT_SET_MESSAGE, &mut message as *mut Message as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+// the frontend hands over its callbacks before anything else, options are declared here
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(cb: EnvironmentFn) {
+    callbacks().environment = Some(cb);
+    let palettes = Palette::names().collect::<Vec<_>>().join("|");
+    let values = [
+        CString::new(format!("Speed (instructions per frame); {}", SPEEDS)).unwrap(),
+        CString::new(format!("Quirks; {}", QUIRK_SETS)).unwrap(),
+        CString::new(format!("Palette; {}", palettes)).unwrap(),
+    ];
+    let mut variables = [
+        Variable { key: SPEED_OPTION.as_ptr(), value: values[0].as_ptr() },
+        Variable { key: QUIRKS_OPTION.as_ptr(), value: values[1].as_ptr() },
+        Variable { key: PALETTE_OPTION.as_ptr(), value: values[2].as_ptr() },
+        // the list ends with an empty entry
+        Variable { key: ptr::null(), value: ptr::null() },
+    ];
+    cb(ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: VideoRefreshFn) {
+    callbacks().video = Some(cb);
+}
+// single samples aren't used, every frame's sound goes out in one batch
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: AudioSampleBatchFn) {
+    callbacks().audio_batch = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: InputPollFn) {
+    callbacks().input_poll = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: InputStateFn) {
+    callbacks().input_state = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *core() = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"CHIP-8".as_ptr(),
+        library_version: c"0.1.0".as_ptr(),
+        valid_extensions: c"ch8|c8|rom".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: SCREEN_WIDTH as c_uint,
+            base_height: SCREEN_HEIGHT as c_uint,
+            max_width: SCREEN_WIDTH as c_uint,
+            max_height: SCREEN_HEIGHT as c_uint,
+            aspect_ratio: SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32,
+        },
+        timing: SystemTiming { fps: 60.0, sample_rate: SAMPLE_RATE as f64 },
+    };
+}
+// only the joypad is supported, whatever the frontend plugs in
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = core().as_mut() {
+        core.reset();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let (video, audio_batch, input_poll, input_state) = {
+        let callbacks = callbacks();
+        (callbacks.video, callbacks.audio_batch, callbacks.input_poll, callbacks.input_state)
+    };
+    let mut updated = false;
+    environment(ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void);
+    let mut core = core();
+    let Some(core) = core.as_mut() else {
+        return;
+    };
+    if updated {
+        core.apply_options();
+    }
+    if let Some(input_poll) = input_poll {
+        unsafe { input_poll() };
+    }
+    core.run_frame(input_state);
+    if let Some(video) = video {
+        let pitch = SCREEN_WIDTH * 4;
+        unsafe { video(core.frame.as_ptr() as *const c_void, SCREEN_WIDTH as c_uint, SCREEN_HEIGHT as c_uint, pitch) };
+    }
+    if let Some(audio_batch) = audio_batch {
+        unsafe { audio_batch(core.audio.as_ptr(), SAMPLES_PER_FRAME) };
+    }
+}
+// every state is the same size, whatever the game
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    SAVE_STATE_SIZE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = core();
+    let Some(core) = core.as_ref() else {
+        return false;
+    };
+    if data.is_null() || size < SAVE_STATE_SIZE {
+        return false;
+    }
+    let state = core.emu.save_state().to_bytes();
+    slice::from_raw_parts_mut(data as *mut u8, state.len()).copy_from_slice(&state);
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = core();
+    let Some(core) = core.as_mut() else {
+        return false;
+    };
+    if data.is_null() {
+        return false;
+    }
+    match SaveState::from_bytes(slice::from_raw_parts(data as *const u8, size)) {
+        Ok(state) => {
+            core.emu.load_state(&state);
+            core.halted = false;
+            true
+        },
+        Err(_) => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    let Some(game) = game.as_ref() else {
+        return false;
+    };
+    if game.data.is_null() {
+        return false;
+    }
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    if !environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        return false;
+    }
+    let rom = slice::from_raw_parts(game.data as *const u8, game.size);
+    let Ok(core_state) = Core::new(rom) else {
+        return false;
+    };
+    core_state.describe_input();
+    *core() = Some(core_state);
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const GameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *core() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+// memory isn't shared with the frontend (no battery saves on a chip-8)
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
@@ -0,0 +1,247 @@
+use chip8_core::*;
+use numpy::{PyArray1, PyArray2, PyArrayMethods};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+
+// ticks per frame for games the database doesn't know, the same as the web frontend
+const TICKS_PER_FRAME: u32 = 10;
+
+// the emulator as a Python class, shaped like the wasm crate's EmuWasm so code moves between
+// the two easily, minus the browser parts (canvas, keyboard events, localStorage)
+// and plus what scripts need: the display as a NumPy array, memory, registers and save states
+#[pyclass(name = "Emu", module = "chip8")]
+pub struct EmuPy {
+    chip8: Emu,
+    // bundled game database plus the user's entries, and the running game's profile
+    rom_db: RomDb,
+    profile: RomProfile,
+    // what a scan of the loaded game's code suggests, see chip8_core::analyze
+    analysis: Option<Analysis>,
+}
+
+// a snapshot from Emu.save_state, only good for handing back to Emu.load_state
+#[pyclass(name = "SaveState", module = "chip8", frozen)]
+pub struct SaveStatePy(SaveState);
+
+#[pymethods]
+impl EmuPy {
+    #[new]
+    fn new() -> Self {
+        EmuPy {
+            chip8: Emu::new(),
+            rom_db: RomDb::bundled(),
+            profile: RomProfile::default(),
+            analysis: None,
+        }
+    }
+    // a game doing something no chip-8 could carry on from raises RuntimeError
+    fn tick(&mut self) -> PyResult<()> {
+        self.chip8.try_tick().map_err(|fault| PyRuntimeError::new_err(fault.to_string()))
+    }
+
+    fn tick_timers(&mut self) {
+        self.chip8.tick_timers();
+    }
+    // ticks_per_frame ticks then the timers, as many times as asked
+    // the same as calling tick and tick_timers from Python, only without the per-call overhead
+    fn run_frames(&mut self, frames: u32) -> PyResult<()> {
+        let ticks = self.ticks_per_frame().unwrap_or(TICKS_PER_FRAME);
+        for _ in 0..frames {
+            for _ in 0..ticks {
+                self.tick()?;
+            }
+            self.chip8.tick_timers();
+        }
+        Ok(())
+    }
+    // clears memory too, so the game has to be loaded again
+    fn reset(&mut self) {
+        self.chip8.reset();
+    }
+    // presses or releases a chip-8 key (0x0 to 0xF)
+    fn keypress(&mut self, key: usize, pressed: bool) -> PyResult<()> {
+        if key >= NUM_KEYS {
+            return Err(PyValueError::new_err(format!("There is no key {:#x}", key)));
+        }
+        self.chip8.keypress(key, pressed);
+        Ok(())
+    }
+    // every key at once, bit n = key n
+    fn key_mask(&self) -> u16 {
+        self.chip8.key_mask()
+    }
+
+    fn set_key_mask(&mut self, mask: u16) {
+        self.chip8.set_key_mask(mask);
+    }
+    // bitmask of the keys the running game checks for
+    fn tested_keys(&self) -> u16 {
+        self.chip8.tested_keys()
+    }
+    // what the random number generator starts from, set it before load_game for repeatable runs
+    fn set_seed(&mut self, seed: u64) {
+        self.chip8.set_seed(seed);
+    }
+
+    fn seed(&self) -> u64 {
+        self.chip8.seed()
+    }
+    // also looks the game up in the database and switches to its quirks
+    // starts from a reset, so it can be called again to switch games
+    fn load_game(&mut self, data: &[u8]) -> PyResult<()> {
+        // a game that doesn't load leaves the current one running
+        let mut chip8 = self.chip8.clone();
+        chip8.reset();
+        chip8.load(data).map_err(PyValueError::new_err)?;
+        self.chip8 = chip8;
+        self.profile = self.rom_db.lookup(self.chip8.rom_hash()).cloned().unwrap_or_default();
+        // unknown games get a guess from scanning their code
+        let analysis = analyze(self.chip8.program());
+        self.profile.fill_from(&analysis);
+        self.analysis = Some(analysis);
+        self.chip8.set_quirks(self.profile.quirks().unwrap_or_default());
+        Ok(())
+    }
+    // game info from the database, None for games it doesn't know
+    fn rom_title(&self) -> Option<String> {
+        self.profile.title.clone()
+    }
+
+    fn rom_author(&self) -> Option<String> {
+        self.profile.author.clone()
+    }
+
+    fn key_help(&self) -> Option<String> {
+        self.profile.key_help.clone()
+    }
+    // platform the game seems to be written for ("chip-8", "super-chip" or "xo-chip")
+    // and how sure the scan is about it, from 0 to 1
+    fn detected_platform(&self) -> Option<String> {
+        self.analysis.as_ref().map(|a| a.platform.name().to_string())
+    }
+
+    fn platform_confidence(&self) -> f32 {
+        self.analysis.as_ref().map_or(0.0, |a| a.confidence)
+    }
+    // how many ticks the game wants per frame, None if the database doesn't say
+    fn ticks_per_frame(&self) -> Option<u32> {
+        self.profile.ticks_per_frame
+    }
+    // ROM hash of the loaded game as 16 hex digits, the key for set_rom_profiles
+    fn rom_hash(&self) -> String {
+        format!("{:016x}", self.chip8.rom_hash())
+    }
+    // replaces the user's game profiles with a JSON object keyed by ROM hash,
+    // takes effect on the next load_game
+    fn set_rom_profiles(&mut self, json: &str) -> PyResult<()> {
+        let overrides: RomDb = serde_json::from_str(json).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        overrides.validate().map_err(PyValueError::new_err)?;
+        self.rom_db = RomDb::bundled();
+        self.rom_db.merge(&overrides);
+        Ok(())
+    }
+    // the display as a (32, 64) array of bools, rows first, a copy
+    fn display<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<bool>>> {
+        PyArray1::from_slice(py, self.chip8.get_display()).reshape([SCREEN_HEIGHT, SCREEN_WIDTH])
+    }
+    // a copy of len bytes of memory starting at addr
+    #[pyo3(signature = (addr=0, len=RAM_SIZE))]
+    fn read_memory<'py>(&self, py: Python<'py>, addr: u16, len: usize) -> PyResult<Bound<'py, PyBytes>> {
+        let data = self.chip8.read_ram(addr, len)
+            .ok_or_else(|| PyValueError::new_err(format!("{} bytes at {:#05x} go past the end of memory", len, addr)))?;
+        Ok(PyBytes::new(py, data))
+    }
+
+    fn write_memory(&mut self, addr: u16, data: &[u8]) -> PyResult<()> {
+        self.chip8.debug().write_ram(addr, data).map_err(PyValueError::new_err)
+    }
+    // the CPU registers as a dict: pc, i, v (16 values), sp, stack (sp entries), dt and st
+    fn registers<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let cpu = self.chip8.cpu();
+        let regs = PyDict::new(py);
+        regs.set_item("pc", cpu.pc)?;
+        regs.set_item("i", cpu.i)?;
+        regs.set_item("v", PyList::new(py, cpu.v.iter())?)?;
+        regs.set_item("sp", cpu.sp)?;
+        regs.set_item("stack", cpu.stack[..cpu.sp as usize].to_vec())?;
+        regs.set_item("dt", cpu.dt)?;
+        regs.set_item("st", cpu.st)?;
+        Ok(regs)
+    }
+    // changes only the registers given, all of them or none if one is bad
+    #[pyo3(signature = (*, pc=None, i=None, v=None, stack=None, dt=None, st=None))]
+    fn set_registers(
+        &mut self,
+        pc: Option<u16>,
+        i: Option<u16>,
+        v: Option<Vec<u8>>,
+        stack: Option<Vec<u16>>,
+        dt: Option<u8>,
+        st: Option<u8>,
+    ) -> PyResult<()> {
+        let before = self.chip8.save_state();
+        let result = set_registers(&mut self.chip8.debug(), pc, i, v, stack, dt, st);
+        if result.is_err() {
+            self.chip8.load_state(&before);
+        }
+        result.map_err(PyValueError::new_err)
+    }
+
+    fn save_state(&self) -> SaveStatePy {
+        SaveStatePy(self.chip8.save_state())
+    }
+
+    fn load_state(&mut self, state: &SaveStatePy) {
+        self.chip8.load_state(&state.0);
+    }
+    // FNV hash of the whole emulator state, equal hashes mean the same game from here on
+    fn state_hash(&self) -> u64 {
+        self.chip8.state_hash()
+    }
+}
+
+fn set_registers(
+    debug: &mut Debugger,
+    pc: Option<u16>,
+    i: Option<u16>,
+    v: Option<Vec<u8>>,
+    stack: Option<Vec<u16>>,
+    dt: Option<u8>,
+    st: Option<u8>,
+) -> Result<(), String> {
+    if let Some(pc) = pc {
+        debug.set_pc(pc)?;
+    }
+    if let Some(i) = i {
+        debug.set_i(i)?;
+    }
+    if let Some(v) = v {
+        if v.len() > 16 {
+            return Err("There are only 16 V registers".to_string());
+        }
+        for (reg, value) in v.into_iter().enumerate() {
+            debug.set_v(reg, value)?;
+        }
+    }
+    if let Some(stack) = stack {
+        debug.set_stack(&stack)?;
+    }
+    if let Some(dt) = dt {
+        debug.set_dt(dt);
+    }
+    if let Some(st) = st {
+        debug.set_st(st);
+    }
+    Ok(())
+}
+
+#[pymodule]
+fn chip8(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<EmuPy>()?;
+    module.add_class::<SaveStatePy>()?;
+    module.add("SCREEN_WIDTH", SCREEN_WIDTH)?;
+    module.add("SCREEN_HEIGHT", SCREEN_HEIGHT)?;
+    module.add("NUM_KEYS", NUM_KEYS)?;
+    Ok(())
+}
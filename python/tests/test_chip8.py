@@ -0,0 +1,130 @@
+from pathlib import Path
+
+import numpy as np
+import pytest
+
+import chip8
+
+GAMES = Path(__file__).resolve().parents[2] / "c8games"
+
+
+@pytest.fixture
+def brix():
+    emu = chip8.Emu()
+    emu.set_seed(1)
+    emu.load_game((GAMES / "BRIX").read_bytes())
+    return emu
+
+
+def test_load_looks_up_the_game(brix):
+    assert brix.rom_title() == "Brix"
+    assert brix.rom_hash() == "c86e8ff63fce668c"
+    assert brix.detected_platform() == "chip-8"
+    assert brix.registers()["pc"] == 0x200
+
+
+def test_display_is_a_numpy_array(brix):
+    assert not brix.display().any()
+    brix.run_frames(60)
+    display = brix.display()
+    assert display.shape == (chip8.SCREEN_HEIGHT, chip8.SCREEN_WIDTH)
+    assert display.dtype == np.bool_
+    # the wall of bricks is up
+    assert display.sum() > 100
+
+
+def test_run_frames_is_tick_and_tick_timers(brix):
+    other = chip8.Emu()
+    other.set_seed(1)
+    other.load_game((GAMES / "BRIX").read_bytes())
+    brix.run_frames(30)
+    for _ in range(30):
+        for _ in range(10):
+            other.tick()
+        other.tick_timers()
+    assert brix.state_hash() == other.state_hash()
+    assert np.array_equal(brix.display(), other.display())
+
+
+def test_same_seed_same_game(brix):
+    other = chip8.Emu()
+    other.set_seed(1)
+    other.load_game((GAMES / "BRIX").read_bytes())
+    for emu in (brix, other):
+        emu.keypress(4, True)
+        emu.run_frames(120)
+    assert brix.state_hash() == other.state_hash()
+
+
+def test_keys(brix):
+    brix.run_frames(180)
+    # BRIX moves the paddle with 4 and 6
+    assert brix.tested_keys() == (1 << 4) | (1 << 6)
+    brix.keypress(6, True)
+    assert brix.key_mask() == 1 << 6
+    brix.set_key_mask(0)
+    assert brix.key_mask() == 0
+    with pytest.raises(ValueError):
+        brix.keypress(16, True)
+
+
+def test_memory(brix):
+    assert len(brix.read_memory()) == 4096
+    assert brix.read_memory(0x200, 2) == (GAMES / "BRIX").read_bytes()[:2]
+    brix.write_memory(0xF00, b"\x01\x02\x03")
+    assert brix.read_memory(0xF00, 3) == b"\x01\x02\x03"
+    with pytest.raises(ValueError):
+        brix.read_memory(0xFFF, 2)
+    with pytest.raises(ValueError):
+        brix.write_memory(0xFFF, b"\x00\x00")
+
+
+def test_registers(brix):
+    brix.set_registers(pc=0x300, i=0x400, v=[1, 2, 3], stack=[0x202], dt=5, st=6)
+    regs = brix.registers()
+    assert regs["pc"] == 0x300
+    assert regs["i"] == 0x400
+    assert regs["v"][:4] == [1, 2, 3, 0]
+    assert regs["stack"] == [0x202]
+    assert regs["sp"] == 1
+    assert (regs["dt"], regs["st"]) == (5, 6)
+
+
+def test_bad_registers_change_nothing(brix):
+    before = brix.registers()
+    with pytest.raises(ValueError):
+        brix.set_registers(i=0x400, pc=0x1001)
+    assert brix.registers() == before
+
+
+def test_save_states(brix):
+    brix.run_frames(60)
+    state = brix.save_state()
+    display = brix.display()
+    memory = brix.read_memory()
+    brix.keypress(6, True)
+    brix.run_frames(60)
+    assert brix.read_memory() != memory or not np.array_equal(brix.display(), display)
+    brix.load_state(state)
+    assert np.array_equal(brix.display(), display)
+    assert brix.read_memory() == memory
+
+
+def test_faults_raise(brix):
+    # return with nothing on the stack
+    brix.load_game(b"\x00\xEE")
+    with pytest.raises(RuntimeError, match="empty stack"):
+        brix.tick()
+
+
+def test_too_big(brix):
+    with pytest.raises(ValueError):
+        brix.load_game(bytes(4096))
+
+
+def test_rom_profiles(brix):
+    brix.set_rom_profiles('{"c86e8ff63fce668c": {"ticks_per_frame": 8}}')
+    brix.load_game((GAMES / "BRIX").read_bytes())
+    assert brix.ticks_per_frame() == 8
+    with pytest.raises(ValueError):
+        brix.set_rom_profiles("not json")
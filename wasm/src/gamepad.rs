@@ -0,0 +1,103 @@
+use chip8_core::{Keymap, RomProfile};
+use wasm_bindgen::JsCast;
+use web_sys::{Gamepad, GamepadButton};
+
+// the browser reports up to four pads
+pub const MAX_PADS: usize = 4;
+// how far a stick has to move before it counts as a d-pad press
+const STICK_THRESHOLD: f64 = 0.5;
+// button names for the browser's "standard" gamepad layout, by button index
+// named like SDL's buttons so desktop and web keymaps look the same
+const STANDARD_BUTTONS: [&str; 17] = [
+    "a", "b", "x", "y",
+    "leftshoulder", "rightshoulder", "lefttrigger", "righttrigger",
+    "back", "start", "leftstick", "rightstick",
+    "dpup", "dpdown", "dpleft", "dpright",
+    "guide",
+];
+
+// the Gamepad API has no events for buttons, so the pads are polled once a frame
+pub struct Gamepads {
+    // the running game's layout for each pad
+    game: [Keymap; MAX_PADS],
+    // bindings the user changed through set_gamepad_map
+    user: [Keymap; MAX_PADS],
+    // the two above combined, what poll() reads
+    maps: [Keymap; MAX_PADS],
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        Self {
+            game: [Keymap::gamepad(), Keymap::gamepad(), Keymap::gamepad(), Keymap::gamepad()],
+            user: [Keymap::empty(), Keymap::empty(), Keymap::empty(), Keymap::empty()],
+            maps: [Keymap::gamepad(), Keymap::gamepad(), Keymap::gamepad(), Keymap::gamepad()],
+        }
+    }
+    // switches to a new game's layout, keeping the user's bindings on top
+    pub fn set_profile(&mut self, profile: &RomProfile) {
+        for idx in 0..MAX_PADS {
+            self.game[idx] = profile.gamepad(idx);
+        }
+        self.rebuild();
+    }
+    // replaces the user's bindings for the pad in slot idx (navigator.getGamepads() order)
+    pub fn set_user_map(&mut self, idx: usize, overrides: Keymap) {
+        self.user[idx] = overrides;
+        self.rebuild();
+    }
+
+    fn rebuild(&mut self) {
+        for idx in 0..MAX_PADS {
+            self.maps[idx] = self.game[idx].clone();
+            self.maps[idx].merge(&self.user[idx]);
+        }
+    }
+
+    pub fn map(&self, idx: usize) -> &Keymap {
+        &self.maps[idx]
+    }
+
+    // the chip-8 keys held on any pad right now, bit n = key n
+    pub fn poll(&self) -> u16 {
+        let pads = match web_sys::window().map(|w| w.navigator().get_gamepads()) {
+            Some(Ok(pads)) => pads,
+            _ => return 0,
+        };
+        let mut held = 0;
+        // unplugged slots show up as null
+        for slot in pads.iter() {
+            if let Ok(pad) = slot.dyn_into::<Gamepad>() {
+                let idx = pad.index() as usize;
+                if pad.connected() && idx < MAX_PADS {
+                    self.read_pad(&pad, &self.maps[idx], &mut held);
+                }
+            }
+        }
+        held
+    }
+    // marks every chip-8 key this pad is holding
+    fn read_pad(&self, pad: &Gamepad, keymap: &Keymap, held: &mut u16) {
+        let mut press = |name: &str| {
+            if let Some(k) = keymap.lookup(name) {
+                *held |= 1 << k;
+            }
+        };
+        for (i, button) in pad.buttons().iter().enumerate() {
+            let pressed = button.dyn_into::<GamepadButton>().map(|b| b.pressed()).unwrap_or(false);
+            if pressed {
+                if let Some(name) = STANDARD_BUTTONS.get(i) {
+                    press(name);
+                }
+            }
+        }
+        // the left stick doubles as the d-pad
+        let axes = pad.axes();
+        let x = axes.get(0).as_f64().unwrap_or(0.0);
+        let y = axes.get(1).as_f64().unwrap_or(0.0);
+        if y < -STICK_THRESHOLD { press("dpup"); }
+        if y > STICK_THRESHOLD { press("dpdown"); }
+        if x < -STICK_THRESHOLD { press("dpleft"); }
+        if x > STICK_THRESHOLD { press("dpright"); }
+    }
+}
@@ -1,8 +1,11 @@
 use chip8_core::*;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast;
-use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, KeyboardEvent};
-use js_sys::Uint8Array;
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData, KeyboardEvent};
+use js_sys::{Array, Uint16Array, Uint8Array};
+
+mod gamepad;
+use gamepad::{Gamepads, MAX_PADS};
 
 // this tag tells the compiler that this struct needs to be configured for wasm
 // any function or struct that is going to be called from JS will need to have it
@@ -10,8 +13,36 @@ use js_sys::Uint8Array;
 pub struct EmuWasm {
     chip8: Emu,
     ctx: CanvasRenderingContext2d,
+    // the keymap in use, the game's bindings with the user's on top
+    keymap: Keymap,
+    user_keymap: Keymap,
+    pads: Gamepads,
+    // keys held on the keyboard, the on-screen keypad and the gamepads (bit n = key n)
+    // kept apart and put together in update_keys, so letting go of a key on one
+    // doesn't release it while another is still holding it down
+    keyboard_keys: u16,
+    touch_keys: u16,
+    pad_keys: u16,
+    // bundled game database plus the user's entries, and the running game's profile
+    rom_db: RomDb,
+    profile: RomProfile,
+    // what a scan of the loaded game's code suggests, see chip8_core::analyze
+    analysis: Option<Analysis>,
+    // draws the display in the chosen palette and anti-flicker mode
+    renderer: Renderer,
+    // the loaded game's cheats and the memory search for finding new ones
+    cheats: Cheats,
+    search: Option<MemorySearch>,
 }
 
+// localStorage entry holding the user's keymap overrides as JSON, e.g. {"KeyQ": 4}
+const KEYMAP_STORAGE_KEY: &str = "chip8-keymap";
+// localStorage entry holding the user's game profiles as JSON, keyed by ROM hash
+// e.g. {"624b3eed64313f42": {"ticks_per_frame": 8}}
+const ROMS_STORAGE_KEY: &str = "chip8-roms";
+// localStorage entries holding each game's cheats as text, the ROM hash goes on the end
+const CHEATS_STORAGE_PREFIX: &str = "chip8-cheats-";
+
 #[wasm_bindgen]
 impl EmuWasm {
     #[wasm_bindgen(constructor)]
@@ -31,7 +62,29 @@ impl EmuWasm {
             .dyn_into::<CanvasRenderingContext2d>()
             .unwrap();
 
-        Ok(EmuWasm{chip8, ctx})
+        // default keymap with any overrides the user saved in an earlier visit
+        let user_keymap = load_keymap_overrides().unwrap_or_else(Keymap::empty);
+        let mut keymap = Keymap::default();
+        keymap.merge(&user_keymap);
+
+        let mut rom_db = RomDb::bundled();
+        if let Some(overrides) = load_rom_overrides() {
+            rom_db.merge(&overrides);
+        }
+
+        Ok(EmuWasm{
+            chip8, ctx, keymap, user_keymap,
+            pads: Gamepads::new(),
+            keyboard_keys: 0,
+            touch_keys: 0,
+            pad_keys: 0,
+            rom_db,
+            profile: RomProfile::default(),
+            analysis: None,
+            renderer: Renderer::new(1),
+            cheats: Cheats::default(),
+            search: None,
+        })
     }
     // the following functions are prettyt simple
     // just calling upon the functions that are in chip8_core
@@ -40,9 +93,11 @@ impl EmuWasm {
         self.chip8.tick();
     }
 
+    // the end of a frame, cheats go in here so the next frame sees them
     #[wasm_bindgen]
     pub fn tick_timers(&mut self) {
         self.chip8.tick_timers();
+        self.cheats.apply(&mut self.chip8);
     }
     // reset function that we never used in chip8_core, until now
     #[wasm_bindgen]
@@ -50,61 +105,289 @@ impl EmuWasm {
         self.chip8.reset();
     }
     // keyboard inputs received directly from javascript
+    // uses the physical key (evt.code) so the keypad doesn't move around on other layouts
     #[wasm_bindgen]
     pub fn keypress(&mut self, evt: KeyboardEvent, pressed: bool) {
-        let key = evt.key();
-        if let Some(k) = key2btn(&key) {
-            self.chip8.keypress(k, pressed);
+        if let Some(k) = self.keymap.lookup(&evt.code()) {
+            set_bit(&mut self.keyboard_keys, k, pressed);
+            self.update_keys();
+        }
+    }
+    // replaces the user's keymap overrides with a JSON object of code -> key
+    // and remembers them in localStorage for next time
+    #[wasm_bindgen]
+    pub fn set_keymap(&mut self, json: &str) -> Result<(), JsValue> {
+        let overrides: Keymap = serde_json::from_str(json)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        overrides.validate().map_err(|e| JsValue::from_str(&e))?;
+        if let Some(storage) = local_storage() {
+            storage.set_item(KEYMAP_STORAGE_KEY, json)?;
+        }
+        self.user_keymap = overrides;
+        self.apply_keymap();
+        Ok(())
+    }
+    // current keymap as JSON, handy for building a settings page
+    #[wasm_bindgen]
+    pub fn get_keymap(&self) -> String {
+        serde_json::to_string(&self.keymap).unwrap()
+    }
+    // presses from the on-screen keypad, key is the chip-8 key itself (0x0 to 0xF)
+    #[wasm_bindgen]
+    pub fn touch_key(&mut self, key: usize, pressed: bool) {
+        if key < NUM_KEYS {
+            set_bit(&mut self.touch_keys, key, pressed);
+            self.update_keys();
+        }
+    }
+    // bitmask of the keys the running game checks for, used to highlight the keypad
+    #[wasm_bindgen]
+    pub fn tested_keys(&self) -> u16 {
+        self.chip8.tested_keys()
+    }
+    // reads every connected gamepad, call once per frame before ticking
+    #[wasm_bindgen]
+    pub fn poll_gamepads(&mut self) {
+        self.pad_keys = self.pads.poll();
+        self.update_keys();
+    }
+    // sets the button mapping for one pad as a JSON object of button -> key,
+    // e.g. {"dpup": 1, "dpdown": 4}, unlisted buttons keep their default
+    // pads are numbered like navigator.getGamepads()
+    #[wasm_bindgen]
+    pub fn set_gamepad_map(&mut self, pad: usize, json: &str) -> Result<(), JsValue> {
+        if pad >= MAX_PADS {
+            return Err(JsValue::from_str("Gamepad index out of range"));
+        }
+        let overrides: Keymap = serde_json::from_str(json)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        overrides.validate().map_err(|e| JsValue::from_str(&e))?;
+        self.pads.set_user_map(pad, overrides);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_gamepad_map(&self, pad: usize) -> Option<String> {
+        if pad >= MAX_PADS {
+            return None;
         }
+        Some(serde_json::to_string(self.pads.map(pad)).unwrap())
     }
     // receives and handles a javascript object
     #[wasm_bindgen]
-    pub fn load_game(&mut self, data: Uint8Array) {
-        self.chip8.load(&data.to_vec());
+    // also looks the game up in the database and switches to its quirks and controls
+    // throws if the game is empty or doesn't fit in memory
+    pub fn load_game(&mut self, data: Uint8Array) -> Result<(), JsValue> {
+        self.chip8.load(&data.to_vec()).map_err(|e| JsValue::from_str(&e))?;
+        self.profile = self.rom_db.lookup(self.chip8.rom_hash()).cloned().unwrap_or_default();
+        // unknown games get a guess from scanning their code
+        let analysis = analyze(self.chip8.program());
+        self.profile.fill_from(&analysis);
+        self.analysis = Some(analysis);
+        // quirks from an earlier game don't carry over to one without a profile
+        self.chip8.set_quirks(self.profile.quirks().unwrap_or_default());
+        self.pads.set_profile(&self.profile);
+        self.apply_keymap();
+        self.cheats = load_cheats(self.chip8.rom_hash()).unwrap_or_default();
+        self.search = None;
+        Ok(())
+    }
+    // game info from the database, undefined for games it doesn't know
+    #[wasm_bindgen]
+    pub fn rom_title(&self) -> Option<String> {
+        self.profile.title.clone()
+    }
+
+    #[wasm_bindgen]
+    pub fn rom_author(&self) -> Option<String> {
+        self.profile.author.clone()
+    }
+
+    #[wasm_bindgen]
+    pub fn key_help(&self) -> Option<String> {
+        self.profile.key_help.clone()
+    }
+    // platform the game seems to be written for ("chip-8", "super-chip" or "xo-chip")
+    // and how sure the scan is about it, from 0 to 1
+    #[wasm_bindgen]
+    pub fn detected_platform(&self) -> Option<String> {
+        self.analysis.as_ref().map(|a| a.platform.name().to_string())
+    }
+
+    #[wasm_bindgen]
+    pub fn platform_confidence(&self) -> f32 {
+        self.analysis.as_ref().map_or(0.0, |a| a.confidence)
+    }
+    // how many ticks the game wants per frame, JS falls back to its own default
+    #[wasm_bindgen]
+    pub fn ticks_per_frame(&self) -> Option<u32> {
+        self.profile.ticks_per_frame
+    }
+    // ROM hash of the loaded game as 16 hex digits, the key for set_rom_profiles
+    #[wasm_bindgen]
+    pub fn rom_hash(&self) -> String {
+        format!("{:016x}", self.chip8.rom_hash())
+    }
+    // replaces the user's game profiles with a JSON object keyed by ROM hash
+    // and remembers them in localStorage, takes effect on the next load_game
+    #[wasm_bindgen]
+    pub fn set_rom_profiles(&mut self, json: &str) -> Result<(), JsValue> {
+        let overrides: RomDb = serde_json::from_str(json)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        overrides.validate().map_err(|e| JsValue::from_str(&e))?;
+        if let Some(storage) = local_storage() {
+            storage.set_item(ROMS_STORAGE_KEY, json)?;
+        }
+        self.rom_db = RomDb::bundled();
+        self.rom_db.merge(&overrides);
+        Ok(())
+    }
+    // the registers, stack, timers and held keys as JSON, for a register view
+    #[wasm_bindgen]
+    pub fn cpu_state(&self) -> String {
+        serde_json::to_string(&self.chip8.cpu()).unwrap()
+    }
+    // len bytes of memory from addr, undefined if that runs past the end
+    #[wasm_bindgen]
+    pub fn read_memory(&self, addr: u16, len: usize) -> Option<Uint8Array> {
+        self.chip8.read_ram(addr, len).map(Uint8Array::from)
+    }
+    // starts a memory search with every address as a candidate
+    #[wasm_bindgen]
+    pub fn search_start(&mut self) {
+        self.search = Some(MemorySearch::new(&self.chip8));
+    }
+    // keeps the addresses that are "equal", "changed", "increased" or "decreased" since the
+    // last call, or that hold a value like "3", returns how many are left
+    #[wasm_bindgen]
+    pub fn search_narrow(&mut self, compare: &str) -> Result<usize, JsValue> {
+        let compare = Compare::named(compare)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown comparison: {}", compare)))?;
+        let search = self.search.as_mut()
+            .ok_or_else(|| JsValue::from_str("No search running"))?;
+        Ok(search.narrow(&self.chip8, compare))
+    }
+    // the addresses left in the search, empty if none is running
+    #[wasm_bindgen]
+    pub fn search_results(&self) -> Uint16Array {
+        Uint16Array::from(self.search.as_ref().map_or(&[][..], |search| search.candidates()))
+    }
+    // the loaded game's cheats in the text format, see chip8_core::Cheats
+    #[wasm_bindgen]
+    pub fn get_cheats(&self) -> String {
+        self.cheats.to_string()
+    }
+    // replaces the loaded game's cheats and remembers them in localStorage under its hash
+    #[wasm_bindgen]
+    pub fn set_cheats(&mut self, text: &str) -> Result<(), JsValue> {
+        let cheats = Cheats::parse(text).map_err(|e| JsValue::from_str(&e))?;
+        if let Some(storage) = local_storage() {
+            storage.set_item(&cheats_storage_key(self.chip8.rom_hash()), &cheats.to_string())?;
+        }
+        self.cheats = cheats;
+        Ok(())
+    }
+    // names of the built-in palettes, for filling in a menu
+    #[wasm_bindgen]
+    pub fn palette_names() -> Array {
+        Palette::names().map(JsValue::from_str).collect()
+    }
+    // switches to one of the built-in palettes
+    #[wasm_bindgen]
+    pub fn set_palette(&mut self, name: &str) -> Result<(), JsValue> {
+        let palette = Palette::named(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown palette: {}", name)))?;
+        self.renderer.set_palette(palette);
+        Ok(())
+    }
+    // switches to a palette of the user's own, as a JSON array of four "#rrggbb" colors
+    #[wasm_bindgen]
+    pub fn set_custom_palette(&mut self, json: &str) -> Result<(), JsValue> {
+        let palette = serde_json::from_str(json)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.renderer.set_palette(palette);
+        Ok(())
+    }
+    // anti-flicker mode: "raw", "decay", "blend" or "either-frame"
+    #[wasm_bindgen]
+    pub fn set_display_mode(&mut self, name: &str) -> Result<(), JsValue> {
+        let mode = DisplayMode::named(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown display mode: {}", name)))?;
+        self.renderer.set_display_mode(mode);
+        Ok(())
+    }
+    // share of brightness a pixel loses per frame in decay mode (0 to 1)
+    #[wasm_bindgen]
+    pub fn set_decay(&mut self, decay: f32) {
+        self.renderer.set_decay(decay);
+    }
+    // upscaling filter: "nearest", "scale2x", "scale3x", "hq2x", "scanlines" or "crt"
+    #[wasm_bindgen]
+    pub fn set_filter(&mut self, name: &str) -> Result<(), JsValue> {
+        let filter = Filter::named(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown filter: {}", name)))?;
+        self.renderer.set_filter(filter);
+        Ok(())
+    }
+    // the last frame drawn as PNG bytes, for JS to offer as a download
+    #[wasm_bindgen]
+    pub fn screenshot_png(&self) -> Uint8Array {
+        Uint8Array::from(&self.renderer.frame_png()[..])
+    }
+    // the game's keyboard bindings with the user's on top
+    fn apply_keymap(&mut self) {
+        self.keymap = self.profile.keyboard();
+        self.keymap.merge(&self.user_keymap);
+    }
+    // what every device holds together is what the game sees
+    fn update_keys(&mut self) {
+        self.chip8.set_key_mask(self.keyboard_keys | self.touch_keys | self.pad_keys);
     }
     // rendering the screen
     // to render to the html5 canvas:
     // o obtain the canvas object and its context (object which gets draw functions upon it)
     // o change the /new/ constructor to grab the current window, canvas, and context (like js)
+    // the core renders the whole frame, colors and effects included, into an RGBA image
+    // which goes onto the canvas with a single putImageData
     #[wasm_bindgen]
-    pub fn draw_screen(&mut self, scale: usize) {
-        let disp = self.chip8.get_display();
-        // iterate through every display's pixel
-        for i in 0..(SCREEN_WIDTH * SCREEN_HEIGHT) {
-            // if it is supposed to be white
-            if disp[i] {
-                let x = i % SCREEN_WIDTH;
-                let y = i / SCREEN_WIDTH;
-                // draw it to the screen
-                self.ctx.fill_rect(
-                    (x * scale) as f64,
-                    (y * scale) as f64,
-                    scale as f64,
-                    scale as f64
-                );
-            }
-        }
+    pub fn draw_screen(&mut self, scale: usize) -> Result<(), JsValue> {
+        self.renderer.set_scale(scale);
+        let (width, height) = (self.renderer.width() as u32, self.renderer.height() as u32);
+        let frame = self.renderer.render(self.chip8.get_display());
+        let image = ImageData::new_with_u8_clamped_array_and_sh(Clamped(frame), width, height)?;
+        self.ctx.put_image_data(&image, 0.0, 0.0)
+    }
+}
+fn set_bit(mask: &mut u16, key: usize, on: bool) {
+    if on {
+        *mask |= 1 << key;
+    } else {
+        *mask &= !(1 << key);
     }
 }
-// similar implementation done in desktop, except with JS keyboard events
-fn key2btn(key: &str) -> Option<usize> {
-    match key {
-        "1" => Some(0x1),
-        "2" => Some(0x2),
-        "3" => Some(0x3),
-        "4" => Some(0xC),
-        "q" => Some(0x4),
-        "w" => Some(0x5),
-        "e" => Some(0x6),
-        "r" => Some(0xD),
-        "a" => Some(0x7),
-        "s" => Some(0x8),
-        "d" => Some(0x9),
-        "f" => Some(0xE),
-        "z" => Some(0xA),
-        "x" => Some(0x0),
-        "c" => Some(0xB),
-        "v" => Some(0xF),
-        _   => None,
-    }
-}
\ No newline at end of file
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+// keymap overrides saved by set_keymap, a broken entry is ignored rather than
+// leaving the player without any keys
+fn load_keymap_overrides() -> Option<Keymap> {
+    let json = local_storage()?.get_item(KEYMAP_STORAGE_KEY).ok()??;
+    let keymap: Keymap = serde_json::from_str(&json).ok()?;
+    keymap.validate().ok()?;
+    Some(keymap)
+}
+fn cheats_storage_key(rom_hash: u64) -> String {
+    format!("{}{:016x}", CHEATS_STORAGE_PREFIX, rom_hash)
+}
+// a game's cheats saved by set_cheats, ignored if broken
+fn load_cheats(rom_hash: u64) -> Option<Cheats> {
+    let text = local_storage()?.get_item(&cheats_storage_key(rom_hash)).ok()??;
+    Cheats::parse(&text).ok()
+}
+// game profiles saved by set_rom_profiles, ignored if broken
+fn load_rom_overrides() -> Option<RomDb> {
+    let json = local_storage()?.get_item(ROMS_STORAGE_KEY).ok()??;
+    let db: RomDb = serde_json::from_str(&json).ok()?;
+    db.validate().ok()?;
+    Some(db)
+}
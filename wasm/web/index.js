@@ -1,77 +1,309 @@
-// when we compile with wasm-pack, it will generate the .wasm file..
-// ..and a "glue" JS file that we can use here
-import init, * as wasm from "./wasm.js"
-
-const WIDTH = 64;
-const HEIGHT = 32;
-const SCALE = 15;
-const TICKS_PER_FRAME = 10;
-let anim_frame = 0;
-// fetch the canvas object
-const canvas = document.getElementById("canvas");
-canvas.width = WIDTH * SCALE;
-canvas.height = HEIGHT * SCALE;
-
-const ctx = canvas.getContext("2d");
-ctx.fillStyle = "black";
-ctx.fillRect(0, 0, WIDTH * SCALE, HEIGHT * SCALE);
-
-const input = document.getElementById("fileinput");
-
-async function run() {
-    // initalizes the wasm binary before using it
-    await init();
-    // create emulator backend object
-    let chip8 = new wasm.EmuWasm();
-    
-    document.addEventListener("keydown", (evt) => {
-        chip8.keypress(evt, true);
-    });
-    
-    document.addEventListener("keyup", (evt) => {
-        chip8.keypress(evt, false);
-    });
-    // handle file loading when file input button is clicked
-    input.addEventListener("change", (evt) => {
-        // stop previous game from rendering if one is running
-        if (anim_frame != 0)
-            window.cancelAnimationFrame(anim_frame);
-        // get file path if it exists
-        let file = evt.target.files[0];
-        if (!file) {
-            alert("Failed to read file");
-            return;
-        }
-        // load in game as Uint8Array, send it to .wasm, start main loop
-        let fr = new FileReader();
-        fr.onload = (e) => {
-            let buffer = fr.result;
-            const rom = new Uint8Array(buffer);
-            chip8.reset();
-            chip8.load_game(rom);
-            mainloop(chip8);
-        }
-        fr.readAsArrayBuffer(file);
-    }, false);
-}
-
-function mainloop(chip8) {
-    // only draw every few ticks
-    for (let _ = 0; _ < TICKS_PER_FRAME; _++)
-        chip8.tick();
-    chip8.tick_timers();
-    
-    // clear the canvas before (re)drawing
-    ctx.fillStyle = "black";
-    ctx.fillRect(0, 0, WIDTH * SCALE, HEIGHT * SCALE);
-    // set the draw color to white before rendering the frame
-    ctx.fillStyle = "white";
-    chip8.draw_screen(SCALE);
-    // ensures 60 fps performance
-    // restarts our mainloop when it is time
-    anim_frame = window.requestAnimationFrame(() => {
-        mainloop(chip8); // calls it again
-    });
-}
-
+// when we compile with wasm-pack, it will generate the .wasm file..
+// ..and a "glue" JS file that we can use here
+import init, * as wasm from "./wasm.js"
+
+const WIDTH = 64;
+const HEIGHT = 32;
+const SCALE = 15;
+// used unless the game's profile asks for something else
+const TICKS_PER_FRAME = 10;
+let ticks_per_frame = TICKS_PER_FRAME;
+let anim_frame = 0;
+// fetch the canvas object
+const canvas = document.getElementById("canvas");
+canvas.width = WIDTH * SCALE;
+canvas.height = HEIGHT * SCALE;
+
+const ctx = canvas.getContext("2d");
+ctx.fillStyle = "black";
+ctx.fillRect(0, 0, WIDTH * SCALE, HEIGHT * SCALE);
+
+const input = document.getElementById("fileinput");
+const rominfo = document.getElementById("rominfo");
+const palette_select = document.getElementById("palette");
+const display_mode_select = document.getElementById("displaymode");
+const filter_select = document.getElementById("filter");
+const screenshot_button = document.getElementById("screenshot");
+const show_registers = document.getElementById("showregisters");
+const registers_view = document.getElementById("registers");
+const search_results = document.getElementById("searchresults");
+const search_value = document.getElementById("searchvalue");
+const cheats_text = document.getElementById("cheats");
+// how many search results get listed
+const SHOWN_RESULTS = 20;
+// localStorage entry remembering the chosen palette
+const PALETTE_STORAGE_KEY = "chip8-palette";
+// COSMAC VIP keypad layout, read left to right, top to bottom
+const KEYPAD_LAYOUT = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+const keypad = document.getElementById("keypad");
+// buttons indexed by their chip-8 key
+const keypad_buttons = [];
+
+async function run() {
+    // initalizes the wasm binary before using it
+    await init();
+    // create emulator backend object
+    let chip8 = new wasm.EmuWasm();
+    
+    // typing into the cheat boxes shouldn't play the game
+    const typing = (evt) => evt.target instanceof HTMLInputElement || evt.target instanceof HTMLTextAreaElement;
+    document.addEventListener("keydown", (evt) => {
+        if (!typing(evt))
+            chip8.keypress(evt, true);
+    });
+    
+    document.addEventListener("keyup", (evt) => {
+        if (!typing(evt))
+            chip8.keypress(evt, false);
+    });
+
+    setup_keypad(chip8);
+    setup_palettes(chip8);
+    setup_cheats(chip8);
+    display_mode_select.addEventListener("change", () => {
+        chip8.set_display_mode(display_mode_select.value);
+    });
+    filter_select.addEventListener("change", () => {
+        chip8.set_filter(filter_select.value);
+    });
+    show_registers.addEventListener("change", () => {
+        registers_view.textContent = "";
+    });
+    screenshot_button.addEventListener("click", () => {
+        download(chip8.screenshot_png(), "image/png", "png");
+    });
+    // handle file loading when file input button is clicked
+    input.addEventListener("change", (evt) => {
+        // stop previous game from rendering if one is running
+        if (anim_frame != 0)
+            window.cancelAnimationFrame(anim_frame);
+        // get file path if it exists
+        let file = evt.target.files[0];
+        if (!file) {
+            alert("Failed to read file");
+            return;
+        }
+        // load in game as Uint8Array, send it to .wasm, start main loop
+        let fr = new FileReader();
+        fr.onload = (e) => {
+            let buffer = fr.result;
+            const rom = new Uint8Array(buffer);
+            chip8.reset();
+            try {
+                chip8.load_game(rom);
+            } catch (e) {
+                alert(e);
+                return;
+            }
+            show_rom_info(chip8);
+            cheats_text.value = chip8.get_cheats();
+            search_results.textContent = "";
+            ticks_per_frame = chip8.ticks_per_frame() ?? TICKS_PER_FRAME;
+            mainloop(chip8);
+        }
+        fr.readAsArrayBuffer(file);
+    }, false);
+}
+
+// the memory search buttons and the cheat list of the loaded game
+function setup_cheats(chip8) {
+    const narrow = (compare) => {
+        try {
+            const left = chip8.search_narrow(compare);
+            show_search_results(chip8, left);
+        } catch (e) {
+            search_results.textContent = e;
+        }
+    };
+    document.getElementById("searchstart").addEventListener("click", () => {
+        chip8.search_start();
+        search_results.textContent = "Searching all memory, now change the value in the game";
+    });
+    document.querySelectorAll("button.narrow").forEach((button) => {
+        button.addEventListener("click", () => narrow(button.value));
+    });
+    document.getElementById("searchbyvalue").addEventListener("click", () => {
+        narrow(search_value.value.trim());
+    });
+    document.getElementById("savecheats").addEventListener("click", () => {
+        try {
+            chip8.set_cheats(cheats_text.value);
+            cheats_text.value = chip8.get_cheats();
+        } catch (e) {
+            alert(e);
+        }
+    });
+}
+
+function show_search_results(chip8, left) {
+    const lines = [`${left} addresses left`];
+    const results = chip8.search_results().slice(0, SHOWN_RESULTS);
+    if (left <= SHOWN_RESULTS) {
+        for (const addr of results) {
+            const value = chip8.read_memory(addr, 1)[0];
+            lines.push(`0x${hex(addr, 3)} = ${value} (0x${hex(value, 2)})`);
+        }
+    }
+    search_results.textContent = lines.join("\n");
+}
+
+// fills the palette menu and restores the last palette picked
+function setup_palettes(chip8) {
+    for (const name of wasm.EmuWasm.palette_names()) {
+        const option = document.createElement("option");
+        option.value = name;
+        option.textContent = name;
+        palette_select.appendChild(option);
+    }
+    const saved = localStorage.getItem(PALETTE_STORAGE_KEY);
+    if (saved && palette_select.querySelector(`option[value="${saved}"]`)) {
+        palette_select.value = saved;
+        chip8.set_palette(saved);
+    }
+    palette_select.addEventListener("change", () => {
+        chip8.set_palette(palette_select.value);
+        localStorage.setItem(PALETTE_STORAGE_KEY, palette_select.value);
+    });
+}
+
+// offers bytes from the emulator as a file download, named after the current time
+function download(bytes, type, extension) {
+    const url = URL.createObjectURL(new Blob([bytes], { type: type }));
+    const link = document.createElement("a");
+    link.href = url;
+    link.download = `chip8-${new Date().toISOString().replace(/[:.]/g, "-")}.${extension}`;
+    link.click();
+    URL.revokeObjectURL(url);
+}
+
+// shows what the game database knows about the loaded game
+function show_rom_info(chip8) {
+    const lines = [];
+    const title = chip8.rom_title();
+    if (title) {
+        const author = chip8.rom_author();
+        lines.push(author ? `${title} by ${author}` : title);
+    }
+    const platform = chip8.detected_platform();
+    if (platform && !title)
+        lines.push(`Looks like a ${platform} game (${Math.round(chip8.platform_confidence() * 100)}% sure)`);
+    const help = chip8.key_help();
+    if (help)
+        lines.push(`Controls: ${help}`);
+    rominfo.textContent = lines.join("\n");
+}
+
+// builds the on-screen keypad and hooks it up to the emulator
+// every finger is tracked on its own, so several keys can be held at once
+// and sliding a finger moves the press to whichever key it ends up on
+function setup_keypad(chip8) {
+    for (const key of KEYPAD_LAYOUT) {
+        const button = document.createElement("button");
+        button.textContent = key.toString(16).toUpperCase();
+        button.dataset.key = key;
+        keypad.appendChild(button);
+        keypad_buttons[key] = button;
+    }
+    // touch identifier -> chip-8 key it is currently holding
+    const touches = new Map();
+    // how many touches hold each key, so lifting one of two fingers doesn't release it
+    const holds = new Array(16).fill(0);
+
+    const press = (key) => {
+        if (holds[key]++ == 0) {
+            chip8.touch_key(key, true);
+            keypad_buttons[key].classList.add("pressed");
+        }
+    };
+    const release = (key) => {
+        if (--holds[key] == 0) {
+            chip8.touch_key(key, false);
+            keypad_buttons[key].classList.remove("pressed");
+        }
+    };
+    // chip-8 key under a touch, if any
+    const key_at = (touch) => {
+        const el = document.elementFromPoint(touch.clientX, touch.clientY);
+        return (el && el.parentNode == keypad) ? Number(el.dataset.key) : undefined;
+    };
+    // moves each changed touch onto the key it is over now
+    const update = (evt) => {
+        evt.preventDefault();
+        for (const touch of evt.changedTouches) {
+            const old_key = touches.get(touch.identifier);
+            const new_key = evt.type == "touchend" || evt.type == "touchcancel"
+                ? undefined : key_at(touch);
+            if (old_key === new_key)
+                continue;
+            if (old_key !== undefined)
+                release(old_key);
+            if (new_key !== undefined) {
+                press(new_key);
+                touches.set(touch.identifier, new_key);
+            } else {
+                touches.delete(touch.identifier);
+            }
+        }
+    };
+    for (const type of ["touchstart", "touchmove", "touchend", "touchcancel"])
+        keypad.addEventListener(type, update, { passive: false });
+    // mouse clicks as well, for trying it out without a touch screen
+    keypad.addEventListener("mousedown", (evt) => {
+        const key = evt.target.dataset.key;
+        if (key === undefined)
+            return;
+        press(Number(key));
+        document.addEventListener("mouseup", () => release(Number(key)), { once: true });
+    });
+}
+
+const hex = (n, digits) => n.toString(16).padStart(digits, "0");
+
+// the registers in the same layout as the desktop's F5 dump
+function show_cpu_state(chip8) {
+    const cpu = JSON.parse(chip8.cpu_state());
+    const op = chip8.read_memory(cpu.pc, 2);
+    const v = cpu.v.map((val, n) => `V${n.toString(16).toUpperCase()} ${hex(val, 2)}`);
+    const stack = cpu.stack.slice(0, cpu.sp).map((addr) => "0x" + hex(addr, 3));
+    const keys = cpu.keys.flatMap((held, key) => held ? [key.toString(16).toUpperCase()] : []);
+    registers_view.textContent =
+        `PC 0x${hex(cpu.pc, 3)}  I 0x${hex(cpu.i, 3)}  SP ${cpu.sp}  DT ${hex(cpu.dt, 2)}  ST ${hex(cpu.st, 2)}\n` +
+        v.slice(0, 8).join(" ") + "\n" + v.slice(8).join(" ") + "\n" +
+        `stack ${stack.join(" ")}\nkeys ${keys.join(" ")}\n` +
+        (op ? `next ${hex(op[0] << 8 | op[1], 4)}` : "");
+}
+
+// highlights the keys the running game has checked for so far
+function highlight_keypad(chip8) {
+    const used = chip8.tested_keys();
+    keypad_buttons.forEach((button, key) => {
+        button.classList.toggle("used", (used & (1 << key)) != 0);
+    });
+}
+
+function mainloop(chip8) {
+    // gamepads have no events, so read them once a frame
+    chip8.poll_gamepads();
+    // only draw every few ticks
+    for (let _ = 0; _ < ticks_per_frame; _++)
+        chip8.tick();
+    chip8.tick_timers();
+    
+    // clears and draws in the current palette's colors
+    chip8.draw_screen(SCALE);
+    highlight_keypad(chip8);
+    if (show_registers.checked)
+        show_cpu_state(chip8);
+    // ensures 60 fps performance
+    // restarts our mainloop when it is time
+    anim_frame = window.requestAnimationFrame(() => {
+        mainloop(chip8); // calls it again
+    });
+}
+
 run().catch(console.error);
\ No newline at end of file